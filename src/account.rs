@@ -1,24 +1,62 @@
 use std::{collections::{BTreeMap, btree_map::Iter}, fmt::Display};
 
-use crate::error::ProcessorError;
+use crate::{error::ProcessorError, policy::Policy};
 
 use super:: {
-    TxAmount, TxID, LedgerAction, LedgerItem,
+    ClientID, TxAmount, TxID, LedgerAction, LedgerItem,
     transaction::{Transaction, TransactionDelta},
 };
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+/// Why an account was locked
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum LockReason {
+    /// A chargeback of the transaction
+    Chargeback(TxID),
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
 enum AccountState {
     Active,
-    Locked,
+    Locked(LockReason),
+}
+
+/// Number of successfully processed ledger items of an account by action
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
+pub struct TransactionCounts {
+    pub deposits:       usize,
+    pub withdrawals:    usize,
+    pub disputes:       usize,
+    pub resolves:       usize,
+    pub chargebacks:    usize,
+}
+
+/// Point-in-time view of an account, as sent by `AccountManagerLoadbalancer::dump`
+///
+/// Ordered by client id first
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
+pub struct AccountSnapshot {
+    pub client_id:      ClientID,
+    pub available:      TxAmount,
+    pub held:           TxAmount,
+    pub total:          TxAmount,
+    pub locked:         bool,
+    pub lock_reason:    Option<LockReason>,
+    pub transactions:   TransactionCounts,
+    /// Transaction of the last successfully processed ledger item
+    pub last_activity:  Option<TxID>,
 }
 
+/// Balances and transactions of a single client
+#[derive(Debug, Clone)]
 pub struct Account {
     state:      AccountState,
     available:  TxAmount,
     held:       TxAmount,
 
-    transactions:   BTreeMap<TxID, Transaction>    
+    transactions:   BTreeMap<TxID, Transaction>,
+
+    counts:         TransactionCounts,
+    last_activity:  Option<TxID>,
 }
 
 impl Account {
@@ -30,42 +68,96 @@ impl Account {
             held:           TxAmount::zero(),
 
             transactions:   BTreeMap::new(),
+
+            counts:         TransactionCounts::default(),
+            last_activity:  None,
+        }
+    }
+
+    /// Recreates an account from its balances, transactions and statistics, e.g. from a snapshot
+    ///
+    /// The sum of the available and held funds has to be representable, like for every processed item
+    pub fn restore(
+        lock_reason:    Option<LockReason>,
+        available:      TxAmount,
+        held:           TxAmount,
+        transactions:   BTreeMap<TxID, Transaction>,
+        counts:         TransactionCounts,
+        last_activity:  Option<TxID>,
+    ) -> Self {
+        let state = match lock_reason {
+            Some(reason) => AccountState::Locked(reason),
+            None => AccountState::Active,
+        };
+
+        Account {
+            state,
+            available,
+            held,
+
+            transactions,
+
+            counts,
+            last_activity,
         }
     }
 
-    fn apply_delta_unchecked(&mut self, delta: TransactionDelta) {
-        self.available  += delta.available;
-        self.held       += delta.held;
+    /// Applies the delta without checking the available funds
+    ///
+    /// Fails when the available, held or total amount would overflow, the balances are unchanged in that case
+    fn try_apply_delta(&mut self, delta: TransactionDelta, item: &LedgerItem) -> Result<(), ProcessorError> {
+        let available = self.available.checked_add(delta.available);
+        let held = self.held.checked_add(delta.held);
+
+        match available.zip(held) {
+            Some((available, held)) if available.checked_add(held).is_some() => {
+                self.available  = available;
+                self.held       = held;
+
+                Ok(())
+            },
+            _ => Err(ProcessorError::Overflow(item.client_id, item.tx_id)),
+        }
     }
 
     fn apply_delta(&mut self, delta: TransactionDelta, item: &LedgerItem) -> Result<(), ProcessorError> {
-        if self.available + delta.available < TxAmount::zero() {
+        if self.available.checked_add(delta.available).is_some_and(|available| available < TxAmount::zero()) {
             Err(ProcessorError::InsufficientFunds(item.client_id, item.tx_id))
         } else {
-            self.apply_delta_unchecked(delta);
-
-            Ok(())
+            self.try_apply_delta(delta, item)
         }
     }
 
-    fn lock(&mut self) {
-        self.state = AccountState::Locked;
+    fn lock(&mut self, reason: LockReason) {
+        self.state = AccountState::Locked(reason);
     }
 
-    fn process_internal(&mut self, item: LedgerItem) -> Result<(), ProcessorError> {
-        if let Some(transaction) = self.transactions.get_mut(&item.tx_id) {
+    fn process_internal(&mut self, policy: Policy, item: LedgerItem) -> Result<(), ProcessorError> {
+        if let Some(transaction) = self.transactions.get(&item.tx_id) {
+            // The transaction is only updated once the delta is applied successfully
+            let mut transaction = transaction.clone();
+
             let delta = match item.action {
-                LedgerAction::Dispute => transaction.dispute().map_err(|e| ProcessorError::from((&item, e))),
-                LedgerAction::Resolve => transaction.resolve().map_err(|e| ProcessorError::from((&item, e))),
-                LedgerAction::Chargeback => transaction.chargeback().map_err(|e| ProcessorError::from((&item, e))),
+                LedgerAction::Dispute(amount) => transaction.dispute(policy, amount).map_err(|e| ProcessorError::from((&item, e))),
+                LedgerAction::Resolve => transaction.resolve(policy).map_err(|e| ProcessorError::from((&item, e))),
+                LedgerAction::Chargeback(amount) => transaction.chargeback(policy, amount).map_err(|e| ProcessorError::from((&item, e))),
                 _ => Err(ProcessorError::DuplicateTransaction(item.client_id, item.tx_id)),
             }?;
 
-            if LedgerAction::Chargeback == item.action {
-                self.lock();
+            self.try_apply_delta(delta, &item)?;
+
+            match item.action {
+                LedgerAction::Dispute(_) => self.counts.disputes += 1,
+                LedgerAction::Resolve => self.counts.resolves += 1,
+                LedgerAction::Chargeback(_) => {
+                    self.counts.chargebacks += 1;
+
+                    self.lock(LockReason::Chargeback(item.tx_id));
+                },
+                _ => {},
             }
 
-            self.apply_delta_unchecked(delta);
+            self.transactions.insert(item.tx_id, transaction);
 
             Ok(())
         } else {
@@ -73,7 +165,8 @@ impl Account {
                 LedgerAction::Deposit(amount) => {
                     let (transaction, delta) = Transaction::deposit(amount).map_err(|e| ProcessorError::from((&item, e)))?;
                     
-                    self.apply_delta_unchecked(delta);
+                    self.try_apply_delta(delta, &item)?;
+                    self.counts.deposits += 1;
 
                     Ok(transaction)
                 },
@@ -81,6 +174,7 @@ impl Account {
                     let (transaction, delta) = Transaction::withdraw(amount).map_err(|e| ProcessorError::from((&item, e)))?;
 
                     self.apply_delta(delta, &item)?;
+                    self.counts.withdrawals += 1;
 
                     Ok(transaction)
                 },
@@ -93,16 +187,32 @@ impl Account {
         }
     }
 
+    /// Applies a ledger item with the default policy
     pub fn process(&mut self, item: LedgerItem) -> Result<(), ProcessorError> {
-        if AccountState::Locked == self.state {
+        self.process_with(Policy::default(), item)
+    }
+
+    /// Applies a ledger item, disputes are handled according to the policy
+    pub fn process_with(&mut self, policy: Policy, item: LedgerItem) -> Result<(), ProcessorError> {
+        if self.is_locked() {
             Err(ProcessorError::LockedAccount(item.client_id, item.tx_id))
         } else {
-            self.process_internal(item)
+            self.process_internal(policy, item)?;
+            self.last_activity = Some(item.tx_id);
+
+            Ok(())
         }
     }
 
     pub fn is_locked(&self) -> bool {
-        AccountState::Locked == self.state
+        matches!(self.state, AccountState::Locked(_))
+    }
+
+    pub fn lock_reason(&self) -> Option<LockReason> {
+        match self.state {
+            AccountState::Locked(reason) => Some(reason),
+            AccountState::Active => None,
+        }
     }
 
     pub fn is_active(&self) -> bool {
@@ -118,14 +228,49 @@ impl Account {
         self.held
     }
 
+    /// Sum of the available and held funds
+    ///
+    /// Items are refused with an overflow error when the total would not be representable, so the sum always fits
     pub fn total(&self) -> TxAmount {
-        self.available + self.held
+        self.available.checked_add(self.held).expect("The total of an account is always representable")
     }
 
-    pub fn transactions(&self) -> Iter<TxID, Transaction> {
+    pub fn transactions(&self) -> Iter<'_, TxID, Transaction> {
         self.transactions.iter()
     }
 
+    pub fn transaction(&self, tx_id: TxID) -> Option<&Transaction> {
+        self.transactions.get(&tx_id)
+    }
+
+    /// Number of successfully processed ledger items by action
+    pub fn counts(&self) -> TransactionCounts {
+        self.counts
+    }
+
+    /// Transaction of the last successfully processed ledger item
+    pub fn last_activity(&self) -> Option<TxID> {
+        self.last_activity
+    }
+
+    pub fn snapshot(&self, client_id: ClientID) -> AccountSnapshot {
+        AccountSnapshot {
+            client_id,
+            available:      self.available(),
+            held:           self.held(),
+            total:          self.total(),
+            locked:         self.is_locked(),
+            lock_reason:    self.lock_reason(),
+            transactions:   self.counts,
+            last_activity:  self.last_activity,
+        }
+    }
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Display for Account {
@@ -143,12 +288,13 @@ impl Display for Account {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     
 
-    use crate::{LedgerAction, LedgerItem, TxAmount, error::ProcessorError};
+    use crate::{LedgerAction, LedgerItem, TxAmount, error::ProcessorError, policy::{DisputePolicy, Policy}, transaction::TransactionState};
 
-    use super::Account;
+    use super::{Account, LockReason, TransactionCounts};
 
     fn setup_account(_amount: TxAmount) -> Account {
         let mut account = Account::new();
@@ -211,7 +357,7 @@ mod test {
         let dispute = LedgerItem {
             client_id:  1,
             tx_id:      1,
-            action:     LedgerAction::Dispute,
+            action:     LedgerAction::Dispute(None),
         };
 
         account.process(dispute).unwrap();
@@ -231,7 +377,7 @@ mod test {
         let dispute = LedgerItem {
             client_id:  1,
             tx_id:      1,
-            action:     LedgerAction::Dispute,
+            action:     LedgerAction::Dispute(None),
         };
 
         account.process(dispute).unwrap();
@@ -239,13 +385,14 @@ mod test {
         let chargeback = LedgerItem {
             client_id:  1,
             tx_id:      1,
-            action:     LedgerAction::Chargeback,
+            action:     LedgerAction::Chargeback(None),
         };
 
         account.process(chargeback).unwrap();
 
         assert_eq!(account.is_locked(), true);
         assert_eq!(account.is_active(), false);
+        assert_eq!(account.lock_reason(), Some(LockReason::Chargeback(1)));
         
         assert_eq!(account.available(), TxAmount::zero());
         assert_eq!(account.held(), TxAmount::zero());
@@ -259,7 +406,7 @@ mod test {
         let dispute = LedgerItem {
             client_id:  1,
             tx_id:      1,
-            action:     LedgerAction::Dispute,
+            action:     LedgerAction::Dispute(None),
         };
 
         account.process(dispute).unwrap();
@@ -341,7 +488,7 @@ mod test {
         account.process(LedgerItem {
             client_id:  1,
             tx_id:      2,
-            action:     LedgerAction::Dispute,
+            action:     LedgerAction::Dispute(None),
         }).unwrap();
 
         assert_eq!(account.is_locked(), false);
@@ -402,6 +549,238 @@ mod test {
         assert_eq!(account.available(), TxAmount::new(1000));
         assert_eq!(account.held(), TxAmount::zero());
         assert_eq!(account.total(), TxAmount::new(1000));
+
+        // The refused withdrawal (tx 5) is neither counted nor the last activity
+        assert_eq!(account.counts(), TransactionCounts { deposits: 3, withdrawals: 3, disputes: 1, resolves: 1, chargebacks: 0 });
+        assert_eq!(account.last_activity(), Some(2));
+
+        let snapshot = account.snapshot(1);
+
+        assert_eq!((snapshot.client_id, snapshot.total, snapshot.locked, snapshot.lock_reason), (1, TxAmount::new(1000), false, None));
+    }
+
+    #[test]
+    fn overflow() {
+        let mut account = setup_account(TxAmount::new(10000));
+
+        let deposit = LedgerItem {
+            client_id:  1,
+            tx_id:      2,
+            action:     LedgerAction::Deposit(TxAmount::MAX),
+        };
+
+        assert_eq!(Err(ProcessorError::Overflow(1, 2)), account.process(deposit));
+
+        let deposit = LedgerItem {
+            client_id:  1,
+            tx_id:      3,
+            action:     LedgerAction::Deposit(TxAmount::MAX - TxAmount::new(20000)),
+        };
+
+        account.process(deposit).unwrap();
+
+        assert_eq!(account.available(), TxAmount::MAX - TxAmount::new(10000));
+
+        let dispute = LedgerItem {
+            client_id:  1,
+            tx_id:      1,
+            action:     LedgerAction::Dispute(None),
+        };
+
+        account.process(dispute).unwrap();
+
+        let deposit = LedgerItem {
+            client_id:  1,
+            tx_id:      4,
+            action:     LedgerAction::Deposit(TxAmount::new(10001)),
+        };
+
+        assert_eq!(Err(ProcessorError::Overflow(1, 4)), account.process(deposit));
+
+        assert_eq!(account.is_locked(), false);
+
+        assert_eq!(account.available(), TxAmount::MAX - TxAmount::new(20000));
+        assert_eq!(account.held(), TxAmount::new(10000));
+        assert_eq!(account.total(), TxAmount::MAX - TxAmount::new(10000));
     }
 
-}
\ No newline at end of file
+    /// Account with a deposit of 1.0 (tx 1) and a withdrawal of 0.4 (tx 2) whose withdrawal is disputed
+    fn disputed_withdrawal(policy: Policy) -> (Account, Result<(), ProcessorError>) {
+        let mut account = setup_account(TxAmount::new(10000));
+
+        let withdrawal = LedgerItem {
+            client_id:  1,
+            tx_id:      2,
+            action:     LedgerAction::Withdrawal(TxAmount::new(4000)),
+        };
+
+        account.process_with(policy, withdrawal).unwrap();
+
+        let dispute = LedgerItem {
+            client_id:  1,
+            tx_id:      2,
+            action:     LedgerAction::Dispute(None),
+        };
+
+        let result = account.process_with(policy, dispute);
+
+        (account, result)
+    }
+
+    fn balances(account: &Account) -> (TxAmount, TxAmount) {
+        (account.available(), account.held())
+    }
+
+    #[test]
+    fn dispute_policy_symmetric() {
+        let policy = Policy { dispute: DisputePolicy::Symmetric, ..Policy::default() };
+
+        let (mut account, result) = disputed_withdrawal(policy);
+
+        result.unwrap();
+        assert_eq!(balances(&account), (TxAmount::new(10000), TxAmount::new(-4000)));
+
+        let mut resolved = account.clone();
+
+        resolved.process_with(policy, LedgerItem { client_id: 1, tx_id: 2, action: LedgerAction::Resolve }).unwrap();
+        assert_eq!(balances(&resolved), (TxAmount::new(6000), TxAmount::zero()));
+
+        account.process_with(policy, LedgerItem { client_id: 1, tx_id: 2, action: LedgerAction::Chargeback(None) }).unwrap();
+        assert_eq!(balances(&account), (TxAmount::new(10000), TxAmount::zero()));
+        assert_eq!(account.is_locked(), true);
+    }
+
+    #[test]
+    fn dispute_policy_deny_withdrawals() {
+        let policy = Policy { dispute: DisputePolicy::DenyWithdrawals, ..Policy::default() };
+
+        let (mut account, result) = disputed_withdrawal(policy);
+
+        assert_eq!(result, Err(ProcessorError::DisputedWithdrawal(1, 2)));
+        assert_eq!(balances(&account), (TxAmount::new(6000), TxAmount::zero()));
+
+        // Deposits can still be disputed
+        account.process_with(policy, LedgerItem { client_id: 1, tx_id: 1, action: LedgerAction::Dispute(None) }).unwrap();
+        assert_eq!(balances(&account), (TxAmount::new(-4000), TxAmount::new(10000)));
+    }
+
+    #[test]
+    fn dispute_policy_pending_credit() {
+        let policy = Policy { dispute: DisputePolicy::PendingCredit, ..Policy::default() };
+
+        let (mut account, result) = disputed_withdrawal(policy);
+
+        result.unwrap();
+        assert_eq!(balances(&account), (TxAmount::new(6000), TxAmount::new(4000)));
+        assert_eq!(account.total(), TxAmount::new(10000));
+
+        let mut resolved = account.clone();
+
+        resolved.process_with(policy, LedgerItem { client_id: 1, tx_id: 2, action: LedgerAction::Resolve }).unwrap();
+        assert_eq!(balances(&resolved), (TxAmount::new(6000), TxAmount::zero()));
+
+        account.process_with(policy, LedgerItem { client_id: 1, tx_id: 2, action: LedgerAction::Chargeback(None) }).unwrap();
+        assert_eq!(balances(&account), (TxAmount::new(10000), TxAmount::zero()));
+        assert_eq!(account.is_locked(), true);
+    }
+
+    #[test]
+    fn dispute_cycles() {
+        let mut account = setup_account(TxAmount::new(10000));
+
+        let dispute = LedgerItem { client_id: 1, tx_id: 1, action: LedgerAction::Dispute(None) };
+        let resolve = LedgerItem { client_id: 1, tx_id: 1, action: LedgerAction::Resolve };
+
+        account.process(dispute).unwrap();
+        account.process(resolve).unwrap();
+
+        // A resolved transaction is final by default
+        let mut default = account.clone();
+
+        assert_eq!(
+            default.process(dispute),
+            Err(ProcessorError::InvalidTransactionStateTransition(1, 1, TransactionState::Resolved, TransactionState::Disputed)),
+        );
+
+        let policy = Policy { max_dispute_cycles: 2, ..Policy::default() };
+
+        account.process_with(policy, dispute).unwrap();
+
+        assert_eq!(account.available(), TxAmount::zero());
+        assert_eq!(account.held(), TxAmount::new(10000));
+
+        account.process_with(policy, resolve).unwrap();
+
+        assert_eq!(
+            account.process_with(policy, dispute),
+            Err(ProcessorError::InvalidTransactionStateTransition(1, 1, TransactionState::Resolved, TransactionState::Disputed)),
+        );
+
+        let (_, transaction) = account.transactions().next().unwrap();
+
+        assert_eq!(transaction.dispute_cycles(), 2);
+        assert_eq!(transaction.history(), [
+            TransactionState::Disputed, TransactionState::Resolved, TransactionState::Disputed, TransactionState::Resolved,
+        ]);
+        assert_eq!(account.counts().disputes, 2);
+    }
+
+    #[test]
+    fn partial_dispute() {
+        let mut account = setup_account(TxAmount::new(10000));
+
+        let dispute = |amount| LedgerItem { client_id: 1, tx_id: 1, action: LedgerAction::Dispute(Some(TxAmount::new(amount))) };
+        let chargeback = |amount| LedgerItem { client_id: 1, tx_id: 1, action: LedgerAction::Chargeback(Some(TxAmount::new(amount))) };
+
+        assert_eq!(account.process(dispute(15000)), Err(ProcessorError::InvalidPartialAmount(1, 1, dispute(15000).action, TxAmount::new(10000))));
+        assert_eq!(account.process(dispute(-1000)), Err(ProcessorError::InvalidPartialAmount(1, 1, dispute(-1000).action, TxAmount::new(10000))));
+        assert_eq!(account.process(dispute(0)), Err(ProcessorError::InvalidPartialAmount(1, 1, dispute(0).action, TxAmount::new(10000))));
+
+        account.process(dispute(4000)).unwrap();
+
+        assert_eq!(balances(&account), (TxAmount::new(6000), TxAmount::new(4000)));
+
+        // The chargeback cannot exceed the disputed amount, the rest of the disputed amount is released
+        assert_eq!(account.process(chargeback(5000)), Err(ProcessorError::InvalidPartialAmount(1, 1, chargeback(5000).action, TxAmount::new(4000))));
+        assert_eq!(account.process(chargeback(0)), Err(ProcessorError::InvalidPartialAmount(1, 1, chargeback(0).action, TxAmount::new(4000))));
+
+        account.process(chargeback(2500)).unwrap();
+
+        assert_eq!(balances(&account), (TxAmount::new(7500), TxAmount::zero()));
+        assert_eq!(account.is_locked(), true);
+
+        let (_, transaction) = account.transactions().next().unwrap();
+
+        assert_eq!((transaction.disputed(), transaction.charged_back()), (TxAmount::zero(), TxAmount::new(2500)));
+    }
+
+    #[test]
+    fn partial_withdrawal_dispute() {
+        let policy = Policy { dispute: DisputePolicy::PendingCredit, ..Policy::default() };
+
+        let (mut account, result) = disputed_withdrawal(policy);
+
+        result.unwrap();
+
+        // The whole withdrawal is disputed, resolving it and disputing part of it needs a second dispute cycle
+        let policy = Policy { max_dispute_cycles: 2, ..policy };
+
+        account.process_with(policy, LedgerItem { client_id: 1, tx_id: 2, action: LedgerAction::Resolve }).unwrap();
+
+        // The extremes of the amount range are refused instead of overflowing when the withdrawal is negated
+        for amount in [TxAmount::MIN, TxAmount::MAX].iter().copied() {
+            let dispute = LedgerItem { client_id: 1, tx_id: 2, action: LedgerAction::Dispute(Some(amount)) };
+
+            assert_eq!(account.process_with(policy, dispute), Err(ProcessorError::InvalidPartialAmount(1, 2, dispute.action, TxAmount::new(4000))));
+        }
+
+        account.process_with(policy, LedgerItem { client_id: 1, tx_id: 2, action: LedgerAction::Dispute(Some(TxAmount::new(1000))) }).unwrap();
+
+        assert_eq!(balances(&account), (TxAmount::new(6000), TxAmount::new(1000)));
+
+        account.process_with(policy, LedgerItem { client_id: 1, tx_id: 2, action: LedgerAction::Chargeback(None) }).unwrap();
+
+        assert_eq!(balances(&account), (TxAmount::new(7000), TxAmount::zero()));
+        assert_eq!(account.transactions().nth(1).unwrap().1.charged_back(), TxAmount::new(-1000));
+    }
+}
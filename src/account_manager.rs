@@ -1,28 +1,39 @@
-use std::{collections::{BTreeMap, btree_map::Iter}, convert::TryFrom};
+use std::{cmp::Reverse, collections::{BTreeMap, BinaryHeap, HashMap, btree_map::Iter, hash_map::Entry}, convert::TryFrom, io};
 
-use tokio::{sync::mpsc::{Sender, channel}, task::JoinHandle};
+use tokio::{sync::{mpsc::{Sender, UnboundedReceiver, channel, unbounded_channel}, oneshot}, task::JoinHandle};
 
-use crate::{ClientID, LedgerItem, account::Account, error::ProcessorError, TxAmount};
+use crate::{
+    ClientID, LedgerAction, LedgerItem, TxID, account::{Account, AccountSnapshot}, error::ProcessorError,
+    journal::{Journal, JournalConfig, JournalPosition}, policy::Policy, reject::{self, Origin, Rejection}, snapshot::Snapshot,
+};
 
+/// Accounts of all clients, an account is created by the first item of a client which is processed successfully
 pub struct AccountManager {
-    accounts: BTreeMap<ClientID, Account>
+    accounts: BTreeMap<ClientID, Account>,
+    policy: Policy,
 }
 
 impl AccountManager {
     pub fn new() -> Self {
+        Self::with_policy(Policy::default())
+    }
+
+    /// Creates a manager whose accounts process the items according to the policy
+    pub fn with_policy(policy: Policy) -> Self {
         AccountManager {
             accounts: BTreeMap::new(),
+            policy,
         }
     }
 
     pub fn process(&mut self, item: LedgerItem) -> Result<(), ProcessorError> {
         if let Some(account) = self.accounts.get_mut(&item.client_id) {
-            account.process(item)
+            account.process_with(self.policy, item)
         } else {
             let mut account = Account::new();
             let client_id = item.client_id;
 
-            if let Err(e) = account.process(item) {
+            if let Err(e) = account.process_with(self.policy, item) {
                 Err(e)
             } else {
                 self.accounts.insert(client_id, account);
@@ -32,113 +43,1043 @@ impl AccountManager {
         }
     }
 
-    pub fn iter(&self) -> Iter<ClientID, Account>{
+    /// Adds an account, e.g. restored from a snapshot, replacing an existing account of the client
+    pub fn insert(&mut self, client_id: ClientID, account: Account) -> Option<Account> {
+        self.accounts.insert(client_id, account)
+    }
+
+    pub fn get(&self, client_id: ClientID) -> Option<&Account> {
+        self.accounts.get(&client_id)
+    }
+
+    pub fn iter(&self) -> Iter<'_, ClientID, Account>{
         self.accounts.iter()
     }
 }
 
+impl Default for AccountManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Settings shared by all tasks of an `AccountManagerLoadbalancer`
+#[derive(Debug, Clone, Default)]
+pub struct ManagerConfig {
+    /// Receives the rows refused by the account manager, errors are only printed to stderr otherwise
+    pub rejects: Option<Sender<Rejection>>,
+    /// Journals the processed items and recovers the accounts from an existing journal on spawn
+    pub journal: Option<JournalConfig>,
+    /// Rules applied by the accounts of all tasks
+    pub policy: Policy,
+}
+
 #[derive(Debug)]
 enum AccountManagerMessage {
-    Process(LedgerItem),
-    Dump(Sender<(ClientID, TxAmount, TxAmount, TxAmount, bool)>),
+    /// Item with its sequence number, used to skip items which were already journaled
+    Process(u64, LedgerItem, Option<Origin>),
+    /// Item refused by the loadbalancer, reported like the items refused by the manager
+    Refuse(u64, ProcessorError, Option<Origin>),
+    Dump(Sender<AccountSnapshot>),
+    /// Sends a copy of every account
+    Collect(Sender<(ClientID, Account)>),
+    /// Sends the sequence number of the last journaled item
+    Position(oneshot::Sender<u64>),
     Stop,
 }
 
+/// An `AccountManager` running in its own tokio task, controlled through messages
+///
+/// The task stops when writing the journal fails, the error is returned by the next call sending to the task or by `join`
 pub struct AccountManagerTask {
-    handle: JoinHandle<()>,
+    /// Taken once the task stopped and its result was returned
+    handle: Option<JoinHandle<io::Result<()>>>,
     sender: Sender<AccountManagerMessage>,
+    /// Transaction id of every processed deposit and withdrawal and whether it was accepted, in processing order
+    claims: UnboundedReceiver<(TxID, bool)>,
 }
 
 impl AccountManagerTask {
-    pub fn spawn() -> AccountManagerTask {
+    /// Spawns a task running the manager, the processed items are appended to the journal if there is one
+    ///
+    /// Items with a sequence number up to last_seq are skipped, they are already applied to the manager or were reported
+    pub fn spawn(mut manager: AccountManager, mut journal: Option<Journal>, mut last_seq: u64, config: ManagerConfig) -> AccountManagerTask {
         let (sender, mut receiver) = channel(128);
+        let (claims_sender, claims) = unbounded_channel();
 
         let handle = tokio::spawn(async move {
-            let mut manager = AccountManager::new();
             while let Some(msg) = receiver.recv().await {
                 match msg {
-                    AccountManagerMessage::Process(item) => {
-                        if let Err(e) = manager.process(item) {
-                            eprintln!("{:?}", e);
+                    AccountManagerMessage::Process(seq, item, origin) => {
+                        let claim = matches!(item.action, LedgerAction::Deposit(_) | LedgerAction::Withdrawal(_));
+
+                        // Already applied before the restart, a deposit or withdrawal was accepted if the account has it
+                        if seq <= last_seq {
+                            if claim {
+                                let accepted = manager.get(item.client_id).and_then(|account| account.transaction(item.tx_id)).is_some();
+
+                                let _ = claims_sender.send((item.tx_id, accepted));
+                            }
+
+                            continue;
+                        }
+
+                        let result = manager.process(item);
+
+                        if claim {
+                            let _ = claims_sender.send((item.tx_id, result.is_ok()));
+                        }
+
+                        match result {
+                            Ok(()) => if let Some(journal) = &mut journal {
+                                journal.append(seq, &item)?;
+
+                                last_seq = seq;
+                            },
+                            Err(e) => {
+                                if let Some(journal) = &mut journal {
+                                    journal.append_refused(seq)?;
+
+                                    last_seq = seq;
+                                }
+
+                                report(&config, e, origin).await?;
+                            },
                         }
                     },
+                    AccountManagerMessage::Refuse(seq, e, origin) => {
+                        if seq <= last_seq {
+                            continue;
+                        }
+
+                        if let Some(journal) = &mut journal {
+                            journal.append_refused(seq)?;
+
+                            last_seq = seq;
+                        }
+
+                        report(&config, e, origin).await?;
+                    },
                     AccountManagerMessage::Dump(sender) => {
+                        // The receiver may stop reading early
+                        for (client_id, account) in manager.iter() {
+                            if sender.send(account.snapshot(*client_id)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    AccountManagerMessage::Collect(sender) => {
                         for (client_id, account) in manager.iter() {
-                            sender.send((*client_id, account.available(), account.held(), account.total(), account.is_locked())).await.unwrap()
+                            if sender.send((*client_id, account.clone())).await.is_err() {
+                                break;
+                            }
                         }
                     }
+                    AccountManagerMessage::Position(sender) => {
+                        let _ = sender.send(last_seq);
+                    }
                     AccountManagerMessage::Stop => {
                         break;
                     }
                 }
             }
+
+            if let Some(journal) = &mut journal {
+                journal.sync()?;
+            }
+
+            Ok(())
         });
 
         AccountManagerTask {
-            handle,
+            handle: Some(handle),
             sender,
+            claims,
         }
     }
 
-    pub async fn process(&self, item: LedgerItem) {
-        self.sender.send(AccountManagerMessage::Process(item)).await.unwrap();
+    /// Next reported deposit or withdrawal, if one was processed already
+    fn try_claim(&mut self) -> Option<(TxID, bool)> {
+        self.claims.try_recv().ok()
+    }
+
+    /// Waits for the next reported deposit or withdrawal
+    async fn next_claim(&mut self) -> io::Result<(TxID, bool)> {
+        match self.claims.recv().await {
+            Some(claim) => Ok(claim),
+            None => Err(self.failure().await),
+        }
     }
 
-    pub async fn dump(&self, sender: Sender<(ClientID, TxAmount, TxAmount, TxAmount, bool)>) {
-        self.sender.send(AccountManagerMessage::Dump(sender)).await.unwrap()
+    async fn send(&mut self, msg: AccountManagerMessage) -> io::Result<()> {
+        if self.sender.send(msg).await.is_ok() {
+            Ok(())
+        } else {
+            Err(self.failure().await)
+        }
     }
 
-    pub async fn stop(&self) {
-        self.sender.send(AccountManagerMessage::Stop).await.unwrap();
+    /// Error which stopped the task, once a message could not be sent to it
+    async fn failure(&mut self) -> io::Error {
+        let result = match self.handle.take() {
+            Some(handle) => handle.await.map_err(io::Error::other).and_then(|result| result),
+            None => Ok(()),
+        };
+
+        result.err().unwrap_or_else(|| io::Error::other("The account manager task stopped"))
     }
 
-    pub async fn join(self) {
-        self.handle.await.unwrap()
+    /// Processes an item, items with a sequence number at or below the last journaled one are skipped
+    pub async fn process(&mut self, seq: u64, item: LedgerItem, origin: Option<Origin>) -> io::Result<()> {
+        self.send(AccountManagerMessage::Process(seq, item, origin)).await
     }
+
+    /// Reports an item refused before reaching the manager, skipped like `process`
+    pub async fn refuse(&mut self, seq: u64, error: ProcessorError, origin: Option<Origin>) -> io::Result<()> {
+        self.send(AccountManagerMessage::Refuse(seq, error, origin)).await
+    }
+
+    pub async fn dump(&mut self, sender: Sender<AccountSnapshot>) -> io::Result<()> {
+        self.send(AccountManagerMessage::Dump(sender)).await
+    }
+
+    pub async fn collect(&mut self, sender: Sender<(ClientID, Account)>) -> io::Result<()> {
+        self.send(AccountManagerMessage::Collect(sender)).await
+    }
+
+    /// Sequence number of the last journaled item, all items up to it are applied to the accounts
+    pub async fn position(&mut self) -> io::Result<u64> {
+        let (sender, receiver) = oneshot::channel();
+
+        self.send(AccountManagerMessage::Position(sender)).await?;
+
+        match receiver.await {
+            Ok(seq) => Ok(seq),
+            Err(_) => Err(self.failure().await),
+        }
+    }
+
+    pub async fn stop(&mut self) -> io::Result<()> {
+        self.send(AccountManagerMessage::Stop).await
+    }
+
+    /// Waits for the task to stop, fails if writing the journal failed
+    pub async fn join(self) -> io::Result<()> {
+        match self.handle {
+            Some(handle) => handle.await.map_err(io::Error::other)?,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Prints a refused item and sends it to the rejects channel, fails if the rejects channel is closed
+async fn report(config: &ManagerConfig, error: ProcessorError, origin: Option<Origin>) -> io::Result<()> {
+    eprintln!("{}", error);
+
+    if let (Some(rejects), Some(origin)) = (&config.rejects, origin) {
+        rejects.send(Rejection::processor(origin, &error)).await.map_err(|_| reject::closed())?;
+    }
+
+    Ok(())
 }
 
+/// Index of the task processing the client
+fn shard(mask: ClientID, client_id: ClientID) -> usize {
+    (client_id & mask) as usize
+}
+
+/// Distributes the clients over a power of 2 number of `AccountManagerTask`s based on the client id
+///
+/// Transaction ids are unique over all clients: the loadbalancer records the client of every accepted deposit and withdrawal
+/// and refuses items referencing the transaction of another client. Items of other clients referencing a transaction id
+/// wait until the tasks reported whether the deposits and withdrawals sent with it were accepted.
 pub struct AccountManagerLoadbalancer {
     tasks: Vec<AccountManagerTask>,
 
     mask: ClientID,
+    /// Client of every transaction id, claimed by the first accepted deposit or withdrawal
+    owners: HashMap<TxID, ClientID>,
+    /// Client and number of the deposits and withdrawals sent to a task which were not reported yet
+    pending: HashMap<TxID, (ClientID, usize)>,
+    /// Sequence number of the last submitted item
+    seq: u64,
+    /// Highest sequence number recovered from the journal
+    recovered: u64,
+    /// Run which wrote the journal, if there is one, recorded in the snapshots with the position of every task
+    run: Option<u64>,
+    /// Recorded in the snapshots
+    policy: Policy,
 }
 
 impl AccountManagerLoadbalancer {
 
-    pub fn spawn(count: usize) -> Self {
+    /// Spawns the tasks, recovering their accounts from the journal if one is configured
+    ///
+    /// Fails if the journal cannot be read or was written with a different number of tasks or policy
+    pub fn spawn(count: usize, config: ManagerConfig) -> io::Result<Self> {
+        let snapshot = Snapshot::new(config.policy);
+
+        Self::restore(count, config, snapshot)
+    }
+
+    /// Spawns the tasks starting with the accounts of a snapshot
+    ///
+    /// A configured journal is replayed on top of the snapshot, so the same snapshot has to be used when recovering.
+    /// The entries are only replayed after the position of the snapshot if it was taken during the run of the journal.
+    /// Fails if the snapshot was written with a different dispute policy.
+    pub fn restore(count: usize, config: ManagerConfig, snapshot: Snapshot) -> io::Result<Self> {
         assert!(count.count_ones() == 1, "Number of tasks need to be a power of 2");
 
-        let tasks = (0..count).map(|_| AccountManagerTask::spawn()).collect();
+        // The maximum number of dispute cycles only limits the next disputes, it can change between runs
+        if snapshot.policy.dispute != config.policy.dispute {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The snapshot was written with the dispute policy {}, not {}", snapshot.policy.dispute, config.policy.dispute),
+            ));
+        }
+
+        let run = match &config.journal {
+            Some(journal) => Some(Journal::prepare(&journal.directory, count, config.policy)?),
+            None => None,
+        };
+
+        // Sequence number of the last item of every task which is contained in the snapshot
+        let positions = match (run, snapshot.journal) {
+            (Some(run), Some(position)) if position.run == run => {
+                if position.seqs.len() != count {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "The snapshot was taken with a different number of tasks than the journal"));
+                }
+
+                position.seqs
+            },
+            _ => vec![0; count],
+        };
+
         let mask = ClientID::try_from(count - 1).unwrap();
 
-        AccountManagerLoadbalancer {
+        let mut managers = (0..count).map(|_| AccountManager::with_policy(config.policy)).collect::<Vec<_>>();
+
+        for (client_id, account) in snapshot.accounts {
+            managers[shard(mask, client_id)].insert(client_id, account);
+        }
+
+        let mut tasks = Vec::with_capacity(count);
+        let mut owners = HashMap::new();
+        let mut recovered = 0;
+
+        for (shard, mut manager) in managers.into_iter().enumerate() {
+            let mut last_seq = positions[shard];
+
+            let journal = if let Some(journal_config) = &config.journal {
+                let (journal, entries) = Journal::open(journal_config, shard)?;
+
+                // The snapshot already contains the items up to its position
+                for (seq, item) in entries.into_iter().filter(|(seq, _)| *seq > positions[shard]) {
+                    if let Some(item) = item {
+                        if let Err(e) = manager.process(item) {
+                            eprintln!("Replaying the journal: {}", e);
+                        }
+                    }
+
+                    last_seq = last_seq.max(seq);
+                }
+
+                Some(journal)
+            } else {
+                None
+            };
+
+            for (client_id, account) in manager.iter() {
+                owners.extend(account.transactions().map(|(tx_id, _)| (*tx_id, *client_id)));
+            }
+
+            tasks.push(AccountManagerTask::spawn(manager, journal, last_seq, config.clone()));
+            recovered = recovered.max(last_seq);
+        }
+
+        Ok(AccountManagerLoadbalancer {
             tasks,
             mask,
+            owners,
+            pending: HashMap::new(),
+            seq: 0,
+            recovered,
+            run,
+            policy: config.policy,
+        })
+    }
+
+    /// Highest sequence number recovered from the journal
+    ///
+    /// Submitting the same items again skips the first `recovered` ones, which were already applied
+    pub fn recovered(&self) -> u64 {
+        self.recovered
+    }
+
+    /// Client which owns the transaction, if a deposit or withdrawal with the transaction id was accepted
+    ///
+    /// Waits until the deposits and withdrawals with the transaction id which are still being processed were reported
+    pub async fn owner(&mut self, tx_id: TxID) -> io::Result<Option<ClientID>> {
+        self.settle_claims(tx_id).await?;
+
+        Ok(self.owners.get(&tx_id).copied())
+    }
+
+    /// Index of the task processing the client which owns the transaction, like `owner`
+    pub async fn owning_shard(&mut self, tx_id: TxID) -> io::Result<Option<usize>> {
+        let mask = self.mask;
+
+        Ok(self.owner(tx_id).await?.map(|client_id| shard(mask, client_id)))
+    }
+
+    /// Records a deposit or withdrawal reported by a task, the first accepted one claims the transaction id
+    fn claim(&mut self, tx_id: TxID, accepted: bool) {
+        if let Entry::Occupied(mut entry) = self.pending.entry(tx_id) {
+            let (client_id, count) = entry.get_mut();
+
+            if accepted {
+                self.owners.entry(tx_id).or_insert(*client_id);
+            }
+
+            *count -= 1;
+
+            if *count == 0 {
+                entry.remove();
+            }
         }
     }
 
-    pub async fn process(&self, item: LedgerItem) {
-        let index = item.client_id & self.mask;
+    /// Records the deposits and withdrawals reported so far
+    fn poll_claims(&mut self) {
+        for index in 0..self.tasks.len() {
+            while let Some((tx_id, accepted)) = self.tasks[index].try_claim() {
+                self.claim(tx_id, accepted);
+            }
+        }
+    }
 
-        self.tasks[index as usize].process(item).await;
+    /// Waits until all deposits and withdrawals with the transaction id sent to a task were reported
+    async fn settle_claims(&mut self, tx_id: TxID) -> io::Result<()> {
+        self.poll_claims();
+
+        while let Some((client_id, _)) = self.pending.get(&tx_id).copied() {
+            let (tx_id, accepted) = self.tasks[shard(self.mask, client_id)].next_claim().await?;
+
+            self.claim(tx_id, accepted);
+        }
+
+        Ok(())
     }
 
-    pub async fn dump(&self, sender: Sender<(ClientID, TxAmount, TxAmount, TxAmount, bool)>) {
-        for task in self.tasks.iter() {
-            task.dump(sender.clone()).await;
+    /// Processes an item, the origin is reported to the rejects channel when the item is refused
+    ///
+    /// Fails if the task of the client stopped because writing its journal failed
+    pub async fn process(&mut self, item: LedgerItem, origin: Option<Origin>) -> io::Result<()> {
+        self.seq += 1;
+
+        self.poll_claims();
+
+        // Deposits and withdrawals of the same client are checked by its account in order
+        let other_client = self.pending.get(&item.tx_id).is_some_and(|(client_id, _)| *client_id != item.client_id);
+
+        if other_client && !self.owners.contains_key(&item.tx_id) {
+            self.settle_claims(item.tx_id).await?;
+        }
+
+        match self.owners.get(&item.tx_id).copied() {
+            Some(owner) if owner != item.client_id => {
+                let error = match item.action {
+                    LedgerAction::Deposit(_) | LedgerAction::Withdrawal(_) => ProcessorError::DuplicateTransaction(item.client_id, item.tx_id),
+                    action => ProcessorError::ClientMismatch(item.client_id, item.tx_id, action, owner),
+                };
+
+                self.tasks[shard(self.mask, item.client_id)].refuse(self.seq, error, origin).await
+            },
+            _ => {
+                if let LedgerAction::Deposit(_) | LedgerAction::Withdrawal(_) = item.action {
+                    self.pending.entry(item.tx_id).or_insert((item.client_id, 0)).1 += 1;
+                }
+
+                self.tasks[shard(self.mask, item.client_id)].process(self.seq, item, origin).await
+            },
         }
     }
 
-    pub async fn stop(&self) {
-        for task in self.tasks.iter() {
-            task.stop().await;
+    /// Sends the balances of all accounts, grouped by task
+    ///
+    /// The order of the clients depends on the number of tasks, see `dump_sorted`
+    pub async fn dump(&mut self, sender: Sender<AccountSnapshot>) -> io::Result<()> {
+        for task in self.tasks.iter_mut() {
+            task.dump(sender.clone()).await?;
         }
+
+        Ok(())
     }
 
-    pub async fn join(self) {
+    /// Sends the balances of all accounts sorted by client id
+    ///
+    /// Every task dumps its accounts in order into its own channel, a separate task merges the channels
+    pub async fn dump_sorted(&mut self, sender: Sender<AccountSnapshot>) -> io::Result<()> {
+        let mut receivers = Vec::with_capacity(self.tasks.len());
+
+        for task in self.tasks.iter_mut() {
+            let (task_sender, receiver) = channel(128);
+
+            task.dump(task_sender).await?;
+            receivers.push(receiver);
+        }
+
+        tokio::spawn(async move {
+            // Next account of every task, ordered by client id
+            let mut heap = BinaryHeap::with_capacity(receivers.len());
+
+            for (index, receiver) in receivers.iter_mut().enumerate() {
+                if let Some(account) = receiver.recv().await {
+                    heap.push(Reverse((account, index)));
+                }
+            }
+
+            while let Some(Reverse((account, index))) = heap.pop() {
+                if sender.send(account).await.is_err() {
+                    break;
+                }
+
+                if let Some(account) = receivers[index].recv().await {
+                    heap.push(Reverse((account, index)));
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Copies the accounts of all tasks
+    ///
+    /// The snapshot contains exactly the items processed before the call, with a journal it records the position of every task
+    pub async fn snapshot(&mut self) -> io::Result<Snapshot> {
+        let (sender, mut receiver) = channel(128);
+
+        for task in self.tasks.iter_mut() {
+            task.collect(sender.clone()).await?;
+        }
+
+        drop(sender);
+
+        let mut snapshot = Snapshot::new(self.policy);
+
+        while let Some((client_id, account)) = receiver.recv().await {
+            snapshot.accounts.insert(client_id, account);
+        }
+
+        if let Some(run) = self.run {
+            let mut seqs = Vec::with_capacity(self.tasks.len());
+
+            for task in self.tasks.iter_mut() {
+                seqs.push(task.position().await?);
+            }
+
+            snapshot.journal = Some(JournalPosition { run, seqs });
+        }
+
+        Ok(snapshot)
+    }
+
+    pub async fn stop(&mut self) -> io::Result<()> {
+        for task in self.tasks.iter_mut() {
+            task.stop().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits for all tasks to stop, fails if writing a journal failed
+    pub async fn join(self) -> io::Result<()> {
         for task in self.tasks {
-            task.join().await;
+            task.join().await?;
+        }
+
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tokio::sync::mpsc::channel;
+
+    use crate::{
+        LedgerAction, LedgerItem, TxAmount, account::Account, journal::{FsyncPolicy, Journal, JournalConfig}, policy::{DisputePolicy, Policy},
+        reject::{Origin, RejectLog}, snapshot::Snapshot,
+    };
+
+    use super::{AccountManagerLoadbalancer, ManagerConfig};
+
+    fn item(client_id: u16, tx_id: u32, action: LedgerAction) -> LedgerItem {
+        LedgerItem { client_id, tx_id, action }
+    }
+
+    async fn balances(manager: &mut AccountManagerLoadbalancer) -> Vec<(u16, TxAmount, TxAmount)> {
+        let (sender, mut receiver) = channel(16);
+
+        manager.dump(sender).await.unwrap();
+
+        let mut balances = Vec::new();
+
+        while let Some(account) = receiver.recv().await {
+            balances.push((account.client_id, account.available, account.held));
+        }
+
+        balances.sort();
+        balances
+    }
+
+    #[tokio::test]
+    async fn recovery() {
+        let directory = std::env::temp_dir().join(format!("transaction-processor-recovery-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let config = ManagerConfig {
+            journal: Some(JournalConfig { directory: directory.clone(), fsync: FsyncPolicy::Always }),
+            ..ManagerConfig::default()
+        };
+
+        let items = [
+            item(1, 1, LedgerAction::Deposit(TxAmount::new(50000))),
+            item(2, 2, LedgerAction::Deposit(TxAmount::new(20000))),
+            item(2, 5, LedgerAction::Withdrawal(TxAmount::new(30000))),
+            item(1, 3, LedgerAction::Withdrawal(TxAmount::new(10000))),
+            item(2, 2, LedgerAction::Dispute(None)),
+            item(1, 4, LedgerAction::Deposit(TxAmount::new(10000))),
+        ];
+
+        let origin = |line| Some(Origin { file: "input.csv".into(), line, row: String::new() });
+
+        // The first run stops after four items without clearing the journal
+        let (rejects, mut rejected) = channel(16);
+        let mut manager = AccountManagerLoadbalancer::spawn(2, ManagerConfig { rejects: Some(rejects), ..config.clone() }).unwrap();
+
+        for (line, item) in items.iter().enumerate().take(4) {
+            manager.process(*item, origin(line)).await.unwrap();
+        }
+
+        manager.stop().await.unwrap();
+        manager.join().await.unwrap();
+
+        assert_eq!(rejected.recv().await.map(|rejection| rejection.line), Some(2));
+        assert!(rejected.recv().await.is_none());
+
+        // The second run recovers the accounts and skips the items which were already applied or reported
+        let (rejects, mut rejected) = channel(16);
+        let mut manager = AccountManagerLoadbalancer::spawn(2, ManagerConfig { rejects: Some(rejects), ..config.clone() }).unwrap();
+
+        assert_eq!(manager.recovered(), 4);
+
+        for (line, item) in items.iter().enumerate() {
+            manager.process(*item, origin(line)).await.unwrap();
+        }
+
+        assert_eq!(balances(&mut manager).await, [
+            (1, TxAmount::new(50000), TxAmount::zero()),
+            (2, TxAmount::zero(), TxAmount::new(20000)),
+        ]);
+
+        manager.stop().await.unwrap();
+        manager.join().await.unwrap();
+
+        assert!(rejected.recv().await.is_none());
+
+        // The journal has to be continued with the same number of tasks and policy
+        let policy = Policy { dispute: DisputePolicy::PendingCredit, ..Policy::default() };
+
+        assert!(AccountManagerLoadbalancer::spawn(2, ManagerConfig { policy, ..config.clone() }).is_err());
+        assert!(AccountManagerLoadbalancer::spawn(4, config).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn snapshot_position() {
+        let directory = std::env::temp_dir().join(format!("transaction-processor-snapshot-position-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let path = directory.join("state.snapshot");
+
+        let config = ManagerConfig {
+            journal: Some(JournalConfig { directory: directory.join("journal"), fsync: FsyncPolicy::Always }),
+            policy: Policy { max_dispute_cycles: 2, ..Policy::default() },
+            ..ManagerConfig::default()
+        };
+
+        let items = [
+            item(1, 1, LedgerAction::Deposit(TxAmount::new(10000))),
+            item(1, 1, LedgerAction::Dispute(None)),
+            item(1, 1, LedgerAction::Resolve),
+            item(1, 1, LedgerAction::Dispute(None)),
+        ];
+
+        // The first run writes its state and fails before clearing the journal
+        let mut manager = AccountManagerLoadbalancer::spawn(2, config.clone()).unwrap();
+
+        for item in items.iter() {
+            manager.process(*item, None).await.unwrap();
+        }
+
+        manager.snapshot().await.unwrap().write(&path).unwrap();
+
+        manager.stop().await.unwrap();
+        manager.join().await.unwrap();
+
+        // The second run restores the state, neither the journal nor the submitted items are applied again
+        let mut manager = AccountManagerLoadbalancer::restore(2, config.clone(), Snapshot::read(&path).unwrap()).unwrap();
+
+        assert_eq!(manager.recovered(), 4);
+
+        for item in items.iter() {
+            manager.process(*item, None).await.unwrap();
+        }
+
+        assert_eq!(balances(&mut manager).await, [(1, TxAmount::zero(), TxAmount::new(10000))]);
+
+        manager.stop().await.unwrap();
+        manager.join().await.unwrap();
+
+        // The position only applies to the journal of the run which took the snapshot
+        Journal::clear(&directory.join("journal")).unwrap();
+
+        let mut manager = AccountManagerLoadbalancer::restore(2, config, Snapshot::read(&path).unwrap()).unwrap();
+
+        assert_eq!(manager.recovered(), 0);
+
+        manager.process(item(1, 2, LedgerAction::Deposit(TxAmount::new(5000))), None).await.unwrap();
+
+        assert_eq!(balances(&mut manager).await, [(1, TxAmount::new(5000), TxAmount::new(10000))]);
+
+        manager.stop().await.unwrap();
+        manager.join().await.unwrap();
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recovered_rejects() {
+        let directory = std::env::temp_dir().join(format!("transaction-processor-recovered-rejects-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let path = directory.join("rejects.csv");
+
+        let journal = JournalConfig { directory: directory.join("journal"), fsync: FsyncPolicy::Always };
+
+        let items = [
+            item(1, 1, LedgerAction::Deposit(TxAmount::new(10000))),
+            item(1, 2, LedgerAction::Withdrawal(TxAmount::new(20000))),
+            item(2, 3, LedgerAction::Dispute(None)),
+        ];
+
+        let origin = |line| Some(Origin { file: "input.csv".into(), line, row: String::new() });
+
+        // Each run stops after the items without clearing the journal, the first one after two items
+        for count in [2, 3] {
+            assert_eq!(Journal::exists(&journal.directory), count == 3);
+
+            let rejects = RejectLog::create(&path, Journal::exists(&journal.directory)).await.unwrap();
+
+            let config = ManagerConfig { rejects: Some(rejects.sender()), journal: Some(journal.clone()), ..ManagerConfig::default() };
+            let mut manager = AccountManagerLoadbalancer::spawn(2, config).unwrap();
+
+            for (line, item) in items.iter().enumerate().take(count) {
+                manager.process(*item, origin(line + 2)).await.unwrap();
+            }
+
+            manager.stop().await.unwrap();
+            manager.join().await.unwrap();
+
+            rejects.join().await.unwrap();
+        }
+
+        let rows = fs::read_to_string(&path).unwrap();
+
+        assert_eq!(rows.lines().map(|row| row.split(',').take(3).collect::<Vec<_>>().join(",")).collect::<Vec<_>>(), [
+            "file,line,code",
+            "\"input.csv\",3,E_INSUFFICIENT_FUNDS",
+            "\"input.csv\",4,E_MISSING_TRANSACTION",
+        ]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restore() {
+        let mut snapshot = Snapshot::default();
+
+        for client_id in 1..=3 {
+            let mut account = Account::new();
+
+            account.process(item(client_id, client_id as u32, LedgerAction::Deposit(TxAmount::new(10000)))).unwrap();
+
+            snapshot.accounts.insert(client_id, account);
+        }
+
+        // The snapshot has to be restored with the policy it was written with
+        let policy = Policy { dispute: DisputePolicy::DenyWithdrawals, ..Policy::default() };
+
+        assert!(AccountManagerLoadbalancer::restore(2, ManagerConfig { policy, ..ManagerConfig::default() }, snapshot.clone()).is_err());
+
+        let policy = Policy { max_dispute_cycles: 2, ..Policy::default() };
+
+        assert!(AccountManagerLoadbalancer::restore(2, ManagerConfig { policy, ..ManagerConfig::default() }, snapshot.clone()).is_ok());
+
+        let mut manager = AccountManagerLoadbalancer::restore(2, ManagerConfig::default(), snapshot).unwrap();
+
+        // Disputes can reference transactions of the restored accounts
+        manager.process(item(2, 2, LedgerAction::Dispute(None)), None).await.unwrap();
+        manager.process(item(4, 4, LedgerAction::Deposit(TxAmount::new(5000))), None).await.unwrap();
+
+        let snapshot = manager.snapshot().await.unwrap();
+
+        assert_eq!(snapshot.accounts.keys().copied().collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(snapshot.accounts[&2].held(), TxAmount::new(10000));
+
+        assert_eq!(balances(&mut manager).await, [
+            (1, TxAmount::new(10000), TxAmount::zero()),
+            (2, TxAmount::zero(), TxAmount::new(10000)),
+            (3, TxAmount::new(10000), TxAmount::zero()),
+            (4, TxAmount::new(5000), TxAmount::zero()),
+        ]);
+
+        manager.stop().await.unwrap();
+        manager.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sorted_dump() {
+        let mut manager = AccountManagerLoadbalancer::spawn(4, ManagerConfig::default()).unwrap();
+
+        for client_id in [7, 2, 12, 5, 1, 8, 3].iter().copied() {
+            manager.process(item(client_id, client_id as u32, LedgerAction::Deposit(TxAmount::new(10000))), None).await.unwrap();
+        }
+
+        let (sender, mut receiver) = channel(2);
+
+        manager.dump_sorted(sender).await.unwrap();
+
+        let mut client_ids = Vec::new();
+
+        while let Some(account) = receiver.recv().await {
+            client_ids.push(account.client_id);
         }
+
+        assert_eq!(client_ids, [1, 2, 3, 5, 7, 8, 12]);
+
+        // The tasks keep running when the receiver stops reading early
+        let (sender, mut receiver) = channel(1);
+
+        manager.dump(sender).await.unwrap();
+        receiver.recv().await.unwrap();
+        drop(receiver);
+
+        let (sender, mut receiver) = channel(1);
+
+        manager.dump_sorted(sender).await.unwrap();
+        receiver.recv().await.unwrap();
+        drop(receiver);
+
+        assert_eq!(manager.snapshot().await.unwrap().accounts.len(), 7);
+
+        manager.stop().await.unwrap();
+        manager.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn closed_rejects() {
+        let (rejects, rejected) = channel(16);
+
+        drop(rejected);
+
+        let mut manager = AccountManagerLoadbalancer::spawn(2, ManagerConfig { rejects: Some(rejects), ..ManagerConfig::default() }).unwrap();
+
+        // The refused withdrawal cannot be reported, the task stops with an error
+        manager.process(item(1, 1, LedgerAction::Withdrawal(TxAmount::new(10000))), Some(Origin { file: "input.csv".into(), line: 1, row: String::new() })).await.unwrap();
+
+        let stopped = manager.stop().await;
+
+        assert!(stopped.is_err() || manager.join().await.is_err());
     }
 
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn client_mismatch() {
+        let mut snapshot = Snapshot::default();
+        let mut account = Account::new();
+
+        account.process(item(1, 1, LedgerAction::Deposit(TxAmount::new(10000)))).unwrap();
+        snapshot.accounts.insert(1, account);
+
+        let (rejects, mut rejected) = channel(16);
+
+        let config = ManagerConfig {
+            rejects: Some(rejects),
+            ..ManagerConfig::default()
+        };
+
+        // Clients 1 and 2 are processed by different tasks
+        let mut manager = AccountManagerLoadbalancer::restore(2, config, snapshot).unwrap();
+
+        let items = [
+            item(2, 1, LedgerAction::Deposit(TxAmount::new(5000))),
+            item(2, 2, LedgerAction::Deposit(TxAmount::new(5000))),
+            item(3, 2, LedgerAction::Withdrawal(TxAmount::new(1000))),
+            item(3, 1, LedgerAction::Dispute(None)),
+            item(2, 3, LedgerAction::Dispute(None)),
+            item(1, 1, LedgerAction::Dispute(None)),
+        ];
+
+        for (line, item) in items.iter().enumerate() {
+            manager.process(*item, Some(Origin { file: "input.csv".into(), line, row: String::new() })).await.unwrap();
+        }
+
+        assert_eq!(balances(&mut manager).await, [
+            (1, TxAmount::zero(), TxAmount::new(10000)),
+            (2, TxAmount::new(5000), TxAmount::zero()),
+        ]);
+
+        assert_eq!((manager.owner(1).await.unwrap(), manager.owning_shard(1).await.unwrap()), (Some(1), Some(1)));
+        assert_eq!((manager.owner(2).await.unwrap(), manager.owning_shard(2).await.unwrap()), (Some(2), Some(0)));
+        assert_eq!(manager.owner(3).await.unwrap(), None);
+
+        manager.stop().await.unwrap();
+        manager.join().await.unwrap();
+
+        let mut codes = Vec::new();
+
+        while let Some(rejection) = rejected.recv().await {
+            if rejection.code == "E_CLIENT_MISMATCH" {
+                assert_eq!(rejection.message, "[Client 3] Tried to dispute transaction with id 1 which belongs to client 1");
+            }
+
+            codes.push((rejection.line, rejection.code));
+        }
+
+        codes.sort();
+
+        assert_eq!(codes, [
+            (0, "E_DUPLICATE_TRANSACTION"),
+            (2, "E_DUPLICATE_TRANSACTION"),
+            (3, "E_CLIENT_MISMATCH"),
+            (4, "E_MISSING_TRANSACTION"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn foreign_dispute() {
+        let (rejects, mut rejected) = channel(16);
+
+        let config = ManagerConfig {
+            rejects: Some(rejects),
+            ..ManagerConfig::default()
+        };
+
+        let mut manager = AccountManagerLoadbalancer::spawn(2, config).unwrap();
+
+        manager.process(item(1, 1, LedgerAction::Deposit(TxAmount::new(10000))), None).await.unwrap();
+        manager.process(item(2, 2, LedgerAction::Deposit(TxAmount::new(5000))), None).await.unwrap();
+
+        // Client 2 disputes, resolves and charges back the deposit of client 1
+        let items = [
+            item(2, 1, LedgerAction::Dispute(None)),
+            item(2, 1, LedgerAction::Resolve),
+            item(2, 1, LedgerAction::Chargeback(None)),
+        ];
+
+        for (line, item) in items.iter().enumerate() {
+            manager.process(*item, Some(Origin { file: "input.csv".into(), line, row: String::new() })).await.unwrap();
+        }
+
+        assert_eq!(manager.owner(1).await.unwrap(), Some(1));
+
+        let (sender, mut receiver) = channel(16);
+
+        manager.dump(sender).await.unwrap();
+
+        let mut accounts = Vec::new();
+
+        while let Some(account) = receiver.recv().await {
+            accounts.push((account.client_id, account.available, account.held, account.locked));
+        }
+
+        accounts.sort();
+
+        assert_eq!(accounts, [
+            (1, TxAmount::new(10000), TxAmount::zero(), false),
+            (2, TxAmount::new(5000), TxAmount::zero(), false),
+        ]);
+
+        manager.stop().await.unwrap();
+        manager.join().await.unwrap();
+
+        let mut rejections = Vec::new();
+
+        while let Some(rejection) = rejected.recv().await {
+            rejections.push((rejection.line, rejection.code, rejection.message));
+        }
+
+        rejections.sort();
+
+        assert_eq!(rejections, [
+            (0, "E_CLIENT_MISMATCH", "[Client 2] Tried to dispute transaction with id 1 which belongs to client 1".to_string()),
+            (1, "E_CLIENT_MISMATCH", "[Client 2] Tried to resolve transaction with id 1 which belongs to client 1".to_string()),
+            (2, "E_CLIENT_MISMATCH", "[Client 2] Tried to chargeback transaction with id 1 which belongs to client 1".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn refused_claim() {
+        let directory = std::env::temp_dir().join(format!("transaction-processor-refused-claim-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        let config = ManagerConfig {
+            journal: Some(JournalConfig { directory: directory.clone(), fsync: FsyncPolicy::Always }),
+            ..ManagerConfig::default()
+        };
+
+        // The refused withdrawal of client 1 does not claim the transaction id for it
+        let items = [
+            item(1, 5, LedgerAction::Withdrawal(TxAmount::new(1000))),
+            item(2, 5, LedgerAction::Deposit(TxAmount::new(3000))),
+            item(1, 5, LedgerAction::Dispute(None)),
+        ];
+
+        let mut runs = Vec::new();
+
+        // The second run recovers from the journal of the first one and submits the same items again
+        for _ in 0..2 {
+            let (rejects, mut rejected) = channel(16);
+            let mut manager = AccountManagerLoadbalancer::spawn(2, ManagerConfig { rejects: Some(rejects), ..config.clone() }).unwrap();
+
+            for (line, item) in items.iter().enumerate() {
+                manager.process(*item, Some(Origin { file: "input.csv".into(), line, row: String::new() })).await.unwrap();
+            }
+
+            let owner = manager.owner(5).await.unwrap();
+            let balances = balances(&mut manager).await;
+
+            manager.stop().await.unwrap();
+            manager.join().await.unwrap();
+
+            let mut codes = Vec::new();
+
+            while let Some(rejection) = rejected.recv().await {
+                codes.push((rejection.line, rejection.code));
+            }
+
+            codes.sort();
+            runs.push((owner, balances, codes));
+        }
+
+        assert_eq!(runs[0], (Some(2), vec![(2, TxAmount::new(3000), TxAmount::zero())], vec![(0, "E_INSUFFICIENT_FUNDS"), (2, "E_CLIENT_MISMATCH")]));
+        assert_eq!(runs[1], (Some(2), vec![(2, TxAmount::new(3000), TxAmount::zero())], vec![]));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,15 +1,25 @@
-use std::fmt::Debug;
-use crate::{ClientID, LedgerAction, LedgerItem, TxID, transaction::{TransactionError, TransactionState}};
+use std::fmt::Display;
+use crate::{ClientID, LedgerAction, LedgerItem, TxAmount, TxID, transaction::{TransactionError, TransactionState}};
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+/// Error returned when a ledger item is refused by an account
+///
+/// Every variant has a stable code (see `ProcessorError::code`) which can be used to aggregate errors
+/// without relying on the human-readable message
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum ProcessorError {
     NegativeDeposit(ClientID, TxID),
     NegativeWithdrawal(ClientID, TxID),
     InvalidTransactionStateTransition(ClientID, TxID, TransactionState, TransactionState),
     DuplicateTransaction(ClientID, TxID),
     MissingTransaction(ClientID, TxID, LedgerAction),
+    /// Client of the row, transaction, action and the client owning the transaction
+    ClientMismatch(ClientID, TxID, LedgerAction, ClientID),
     InsufficientFunds(ClientID, TxID),
     LockedAccount(ClientID, TxID),
+    Overflow(ClientID, TxID),
+    DisputedWithdrawal(ClientID, TxID),
+    /// Client, transaction, partial dispute or chargeback and the amount which can be disputed or charged back
+    InvalidPartialAmount(ClientID, TxID, LedgerAction, TxAmount),
 }
 
 impl From<(&LedgerItem, TransactionError)> for ProcessorError {
@@ -18,27 +28,248 @@ impl From<(&LedgerItem, TransactionError)> for ProcessorError {
             TransactionError::NegativeDeposit => ProcessorError::NegativeDeposit(item.client_id, item.tx_id),
             TransactionError::NegativeWithdrawal => ProcessorError::NegativeWithdrawal(item.client_id, item.tx_id),
             TransactionError::InvalidTransactionStateTransition(src, dst) => ProcessorError::InvalidTransactionStateTransition(item.client_id, item.tx_id, src, dst),
+            TransactionError::DisputedWithdrawal => ProcessorError::DisputedWithdrawal(item.client_id, item.tx_id),
+            TransactionError::InvalidPartialAmount(remaining) => ProcessorError::InvalidPartialAmount(item.client_id, item.tx_id, item.action, remaining),
         }
     }
 }
 
-impl Debug for ProcessorError {
+impl ProcessorError {
+    /// Stable code identifying the kind of error
+    ///
+    /// Codes are never changed or reused once released, new variants get a new code
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProcessorError::NegativeDeposit(..) => "E_NEGATIVE_DEPOSIT",
+            ProcessorError::NegativeWithdrawal(..) => "E_NEGATIVE_WITHDRAWAL",
+            ProcessorError::InvalidTransactionStateTransition(..) => "E_INVALID_STATE_TRANSITION",
+            ProcessorError::DuplicateTransaction(..) => "E_DUPLICATE_TRANSACTION",
+            ProcessorError::MissingTransaction(..) => "E_MISSING_TRANSACTION",
+            ProcessorError::ClientMismatch(..) => "E_CLIENT_MISMATCH",
+            ProcessorError::InsufficientFunds(..) => "E_INSUFFICIENT_FUNDS",
+            ProcessorError::LockedAccount(..) => "E_LOCKED_ACCOUNT",
+            ProcessorError::Overflow(..) => "E_OVERFLOW",
+            ProcessorError::DisputedWithdrawal(..) => "E_DISPUTED_WITHDRAWAL",
+            ProcessorError::InvalidPartialAmount(..) => "E_INVALID_PARTIAL_AMOUNT",
+        }
+    }
+
+    pub fn client_id(&self) -> ClientID {
+        match self {
+            ProcessorError::NegativeDeposit(client_id, ..)
+            | ProcessorError::NegativeWithdrawal(client_id, ..)
+            | ProcessorError::InvalidTransactionStateTransition(client_id, ..)
+            | ProcessorError::DuplicateTransaction(client_id, ..)
+            | ProcessorError::MissingTransaction(client_id, ..)
+            | ProcessorError::ClientMismatch(client_id, ..)
+            | ProcessorError::InsufficientFunds(client_id, ..)
+            | ProcessorError::LockedAccount(client_id, ..)
+            | ProcessorError::Overflow(client_id, ..)
+            | ProcessorError::DisputedWithdrawal(client_id, ..)
+            | ProcessorError::InvalidPartialAmount(client_id, ..) => *client_id,
+        }
+    }
+
+    pub fn tx_id(&self) -> TxID {
+        match self {
+            ProcessorError::NegativeDeposit(_, tx_id, ..)
+            | ProcessorError::NegativeWithdrawal(_, tx_id, ..)
+            | ProcessorError::InvalidTransactionStateTransition(_, tx_id, ..)
+            | ProcessorError::DuplicateTransaction(_, tx_id, ..)
+            | ProcessorError::MissingTransaction(_, tx_id, ..)
+            | ProcessorError::ClientMismatch(_, tx_id, ..)
+            | ProcessorError::InsufficientFunds(_, tx_id, ..)
+            | ProcessorError::LockedAccount(_, tx_id, ..)
+            | ProcessorError::Overflow(_, tx_id, ..)
+            | ProcessorError::DisputedWithdrawal(_, tx_id, ..)
+            | ProcessorError::InvalidPartialAmount(_, tx_id, ..) => *tx_id,
+        }
+    }
+}
+
+impl Display for ProcessorError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ProcessorError::NegativeDeposit(client_id, tx_id) => 
                 write!(f, "[Client {}] Transaction {} (Deposit) has a negative value", client_id, tx_id),
             ProcessorError::NegativeWithdrawal(client_id, tx_id) =>
-                write!(f, "[Client {}]  Transaction {} (Withdrawal) has a negative value", client_id, tx_id),
+                write!(f, "[Client {}] Transaction {} (Withdrawal) has a negative value", client_id, tx_id),
             ProcessorError::InvalidTransactionStateTransition(client_id, tx_id, orig, new) =>
-                write!(f, "[Client {}]  Transition {} from state {} to {} is not possible", client_id, tx_id, orig, new),
+                write!(f, "[Client {}] Transition {} from state {} to {} is not possible", client_id, tx_id, orig, new),
             ProcessorError::DuplicateTransaction(client_id, tx_id) =>
                 write!(f, "[Client {}] Tried to add a duplicate transaction with id {}", client_id, tx_id),
             ProcessorError::MissingTransaction(client_id, tx_id, action) =>
                 write!(f, "[Client {}] Tried to {} transaction with id {} but transaction is not found", client_id, action, tx_id),
+            ProcessorError::ClientMismatch(client_id, tx_id, action, owner) =>
+                write!(f, "[Client {}] Tried to {} transaction with id {} which belongs to client {}", client_id, action, tx_id, owner),
             ProcessorError::InsufficientFunds(client_id, tx_id) =>
                 write!(f, "[Client {}] Insufficient funds to process transaction {}", client_id, tx_id),
             ProcessorError::LockedAccount(client_id, tx_id) =>
                 write!(f, "[Client {}] Cannot process transaction {} because the account is locked", client_id, tx_id),
+            ProcessorError::Overflow(client_id, tx_id) =>
+                write!(f, "[Client {}] Processing transaction {} would overflow the account balance", client_id, tx_id),
+            ProcessorError::DisputedWithdrawal(client_id, tx_id) =>
+                write!(f, "[Client {}] Tried to dispute transaction with id {} but disputes of withdrawals are not allowed", client_id, tx_id),
+            ProcessorError::InvalidPartialAmount(client_id, tx_id, action, remaining) =>
+                write!(f, "[Client {}] Tried to {} transaction with id {} but the amount has to be larger than 0 and at most {}", client_id, action, tx_id, remaining),
         }
     }
-}
\ No newline at end of file
+}
+
+impl std::error::Error for ProcessorError {}
+
+/// Serializes the error as a map of its code, client, transaction and message
+#[cfg(feature = "serde")]
+impl serde::Serialize for ProcessorError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("ProcessorError", 4)?;
+
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("client", &self.client_id())?;
+        state.serialize_field("tx", &self.tx_id())?;
+        state.serialize_field("message", &self.to_string())?;
+
+        state.end()
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ParseErrorKind {
+    MissingColumn(&'static str),
+    DuplicateColumn(&'static str),
+    MalformedField,
+    MissingField,
+    UnexpectedField,
+    UnexpectedAmount,
+    DuplicateField,
+    UnknownType,
+    InvalidClientId,
+    InvalidTxId,
+    InvalidAmount,
+    TooManyDecimals,
+    Overflow,
+}
+
+/// Error returned when a line of the input cannot be decoded
+///
+/// Points to the offending field by its physical line number (starting at 1, including the header)
+/// and the byte offset of the field within the line
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ParseError {
+    pub line:   usize,
+    pub offset: usize,
+    pub column: Option<&'static str>,
+    pub field:  String,
+    pub kind:   ParseErrorKind,
+}
+
+impl ParseErrorKind {
+    /// Short code identifying the kind of error, used in the rejects file
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseErrorKind::MissingColumn(_) => "E_PARSE_MISSING_COLUMN",
+            ParseErrorKind::DuplicateColumn(_) => "E_PARSE_DUPLICATE_COLUMN",
+            ParseErrorKind::MalformedField => "E_PARSE_MALFORMED_FIELD",
+            ParseErrorKind::MissingField => "E_PARSE_MISSING_FIELD",
+            ParseErrorKind::UnexpectedField => "E_PARSE_UNEXPECTED_FIELD",
+            ParseErrorKind::UnexpectedAmount => "E_PARSE_UNEXPECTED_AMOUNT",
+            ParseErrorKind::DuplicateField => "E_PARSE_DUPLICATE_FIELD",
+            ParseErrorKind::UnknownType => "E_PARSE_UNKNOWN_TYPE",
+            ParseErrorKind::InvalidClientId => "E_PARSE_INVALID_CLIENT_ID",
+            ParseErrorKind::InvalidTxId => "E_PARSE_INVALID_TX_ID",
+            ParseErrorKind::InvalidAmount => "E_PARSE_INVALID_AMOUNT",
+            ParseErrorKind::TooManyDecimals => "E_PARSE_TOO_MANY_DECIMALS",
+            ParseErrorKind::Overflow => "E_PARSE_OVERFLOW",
+        }
+    }
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, offset: usize, column: Option<&'static str>, field: &str) -> Self {
+        ParseError {
+            line:   0,
+            offset,
+            column,
+            field:  field.to_string(),
+            kind,
+        }
+    }
+
+    /// Sets the physical line number the error occurred on
+    pub fn at_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseErrorKind::MissingColumn(column) => write!(f, "header is missing the required column \"{}\"", column),
+            ParseErrorKind::DuplicateColumn(column) => write!(f, "header contains the column \"{}\" more than once", column),
+            ParseErrorKind::MalformedField => write!(f, "malformed field"),
+            ParseErrorKind::MissingField => write!(f, "missing field"),
+            ParseErrorKind::UnexpectedField => write!(f, "row contains more fields than the header"),
+            ParseErrorKind::UnexpectedAmount => write!(f, "resolves cannot carry an amount"),
+            ParseErrorKind::DuplicateField => write!(f, "row contains the field more than once"),
+            ParseErrorKind::UnknownType => write!(f, "unknown transaction type"),
+            ParseErrorKind::InvalidClientId => write!(f, "invalid client id"),
+            ParseErrorKind::InvalidTxId => write!(f, "invalid transaction id"),
+            ParseErrorKind::InvalidAmount => write!(f, "invalid amount"),
+            ParseErrorKind::TooManyDecimals => write!(f, "amount has too many decimals"),
+            ParseErrorKind::Overflow => write!(f, "value is out of range"),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[Line {}, byte {}] ", self.line, self.offset)?;
+
+        if let Some(column) = self.column {
+            write!(f, "Field {} ({:?}): ", column, self.field)?;
+        }
+
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod test {
+    use crate::{LedgerAction, transaction::TransactionState};
+
+    use super::ProcessorError;
+
+    #[test]
+    fn codes() {
+        assert_eq!(ProcessorError::InsufficientFunds(1, 2).code(), "E_INSUFFICIENT_FUNDS");
+        assert_eq!(ProcessorError::MissingTransaction(1, 2, LedgerAction::Dispute(None)).code(), "E_MISSING_TRANSACTION");
+        assert_eq!(
+            ProcessorError::InvalidTransactionStateTransition(1, 2, TransactionState::New, TransactionState::Resolved).code(),
+            "E_INVALID_STATE_TRANSITION",
+        );
+    }
+
+    #[test]
+    fn display() {
+        let error = ProcessorError::InsufficientFunds(1, 2);
+
+        assert_eq!(error.to_string(), "[Client 1] Insufficient funds to process transaction 2");
+        assert_eq!((error.client_id(), error.tx_id()), (1, 2));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let error = ProcessorError::LockedAccount(3, 4);
+
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"code":"E_LOCKED_ACCOUNT","client":3,"tx":4,"message":"[Client 3] Cannot process transaction 4 because the account is locked"}"#,
+        );
+    }
+}
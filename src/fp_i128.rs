@@ -0,0 +1,49 @@
+use crate::fp_isize::fixed_point;
+
+fixed_point!(FpI128, i128, "Can maximally represent 128 Bit values, multiplications and divisions require the intermediate result to fit in 128 Bit");
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use crate::fp_isize::{FpParseError, RoundingMode};
+
+    use super::FpI128;
+
+    #[test]
+    fn parse() {
+        let number = FpI128::<4>::try_from(("10", "15"));
+
+        assert_eq!(number, Ok(FpI128::new(101500)));
+
+        let number = FpI128::<8>::try_from(("99999999999999999999999", "12345678"));
+
+        assert_eq!(number, Ok(FpI128::new(9999999999999999999999912345678)));
+
+        let max = i128::MAX.to_string();
+        let (integral, fractional) = max.split_at(max.len() - 4);
+
+        assert_eq!(FpI128::<4>::try_from((integral, fractional)), Ok(FpI128::MAX));
+        assert_eq!(FpI128::<4>::try_from((max.as_str(), "0")), Err(FpParseError::Overflow));
+        assert_eq!(FpI128::<4>::try_from(("1", "12345")), Err(FpParseError::ExcessPrecision));
+    }
+
+    #[test]
+    fn format() {
+        let number = FpI128::<8>::new(-9999999999999999999999912345678);
+
+        assert_eq!(format!("{}", number), "-99999999999999999999999.12345678");
+    }
+
+    #[test]
+    fn arithmetic() {
+        let large = FpI128::<4>::new(isize::MAX as i128);
+
+        assert_eq!(large + large, FpI128::new(2 * isize::MAX as i128));
+        assert_eq!(FpI128::<4>::MAX.checked_add(FpI128::new(1)), None);
+
+        assert_eq!(large.checked_mul(FpI128::new(20000), RoundingMode::HalfEven), Some(FpI128::new(2 * isize::MAX as i128)));
+        assert_eq!(FpI128::<4>::new(10000).checked_div(FpI128::new(30000), RoundingMode::HalfEven), Some(FpI128::new(3333)));
+        assert_eq!(FpI128::<4>::MAX.checked_mul(FpI128::new(20000), RoundingMode::HalfEven), None);
+    }
+}
@@ -1,116 +1,435 @@
-use std::{convert::TryFrom, fmt::{Debug, Display}, ops::{Add, AddAssign, Neg, Sub, SubAssign}};
-
-/// Fixed-Point decimal number representation
-///
-/// Implemented to support a precision of up to PRECISION numbers after the decimal point
-/// Can maximally represent 64 Bit values
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
-pub struct FpIsize<const PRECISION: u32> {
-    inner: isize,
+use std::{fmt::Display, str::FromStr};
+
+/// Rounding applied when the result of an operation has more fractional digits than PRECISION
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RoundingMode {
+    /// Round to the nearest value, ties go to the even neighbour (banker's rounding)
+    HalfEven,
+    /// Round to the nearest value, ties go away from zero
+    HalfUp,
+    /// Drop the excess digits
+    TowardZero,
+    /// Round toward negative infinity
+    Floor,
+    /// Round toward positive infinity
+    Ceil,
 }
 
-const fn precision_factor(precision: u32) -> isize {
-    10isize.pow(precision)
+impl FromStr for RoundingMode {
+    type Err = &'static str;
+
+    /// Parses the kebab-case name of the mode, e.g. "half-even"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-even" => Ok(RoundingMode::HalfEven),
+            "half-up" => Ok(RoundingMode::HalfUp),
+            "toward-zero" => Ok(RoundingMode::TowardZero),
+            "floor" => Ok(RoundingMode::Floor),
+            "ceil" => Ok(RoundingMode::Ceil),
+            _ => Err("Unknown rounding mode, expected half-even, half-up, toward-zero, floor or ceil"),
+        }
+    }
 }
 
-impl <const PRECISION: u32> FpIsize<PRECISION> {
+/// Divides numerator by denominator and rounds the quotient according to mode
+///
+/// Returns None when dividing by zero or when the quotient cannot be represented
+pub(crate) fn div_round(numerator: i128, denominator: i128, mode: RoundingMode) -> Option<i128> {
+    let quotient = numerator.checked_div(denominator)?;
+    let remainder = numerator % denominator;
 
-    /// Creates a new TxAmount based on the inner value
-    ///
-    /// The caller is responsible to calculate the correct inner value
-    pub const fn new(inner: isize) -> Self {
-        FpIsize {
-            inner
-        }
+    if remainder == 0 {
+        return Some(quotient);
     }
 
-    /// Creates a new TxAmount with a value of 0
-    pub const fn zero() -> Self {
-        Self::new(0)
+    let negative = (numerator < 0) != (denominator < 0);
+
+    let remainder = remainder.unsigned_abs();
+    let rest = denominator.unsigned_abs() - remainder;
+
+    let away_from_zero = match mode {
+        RoundingMode::HalfEven => remainder > rest || (remainder == rest && quotient % 2 != 0),
+        RoundingMode::HalfUp => remainder >= rest,
+        RoundingMode::TowardZero => false,
+        RoundingMode::Floor => negative,
+        RoundingMode::Ceil => !negative,
+    };
+
+    if !away_from_zero {
+        Some(quotient)
+    } else if negative {
+        quotient.checked_sub(1)
+    } else {
+        quotient.checked_add(1)
     }
 }
 
-impl <const PRECISION: u32> Add for FpIsize<PRECISION> {
-    type Output = Self;
-
-    fn add(self, rhs: Self) -> Self::Output {
-        Self::new(self.inner + rhs.inner)
-    }
+/// Error returned when a string cannot be converted to a fixed-point number
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FpParseError {
+    /// The number contains more fractional digits than defined in PRECISION
+    ExcessPrecision,
+    /// The number is too large to be represented
+    Overflow,
+    /// The number contains a character which is not a decimal digit
+    InvalidDigit,
 }
 
-impl <const PRECISION: u32> AddAssign for FpIsize<PRECISION> {
-    fn add_assign(&mut self, rhs: Self) {
-        self.inner += rhs.inner
+impl Display for FpParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FpParseError::ExcessPrecision => write!(f, "Number should not contain more fractional digits than defined in PRECISION"),
+            FpParseError::Overflow => write!(f, "Number is too large to be represented"),
+            FpParseError::InvalidDigit => write!(f, "Number contains an invalid digit"),
+        }
     }
 }
 
-impl <const PRECISION: u32> Sub for FpIsize<PRECISION> {
-    type Output = Self;
+impl std::error::Error for FpParseError {}
 
-    fn sub(self, rhs: Self) -> Self::Output {
-        Self::new(self.inner - rhs.inner)
+pub(crate) fn parse_digits<T: FromStr + Default>(digits: &str) -> Result<T, FpParseError> {
+    if digits.is_empty() {
+        Ok(T::default())
+    } else if !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+        Err(FpParseError::InvalidDigit)
+    } else {
+        digits.parse::<T>().map_err(|_| FpParseError::Overflow)
     }
 }
 
-impl <const PRECISION: u32> SubAssign for FpIsize<PRECISION> {
-    fn sub_assign(&mut self, rhs: Self) {
-        self.inner -= rhs.inner
-    }
-}
+/// Defines a fixed-point decimal number type backed by the given signed integer type
+///
+/// Intermediate results of multiplications and divisions are calculated in i128
+macro_rules! fixed_point {
+    ($name:ident, $inner:ty, $doc:expr) => {
+        /// Fixed-Point decimal number representation
+        ///
+        /// Implemented to support a precision of up to PRECISION numbers after the decimal point
+        ///
+        /// The `+`, `-` and negation operators overflow like the inner integer type: they panic in debug builds and wrap
+        /// around in release builds. The `checked_*` methods return None instead and are used wherever the result is not
+        /// known to be representable.
+        ///
+        #[doc = $doc]
+        #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+        pub struct $name<const PRECISION: u32> {
+            inner: $inner,
+        }
+
+        impl <const PRECISION: u32> $name<PRECISION> {
+
+            /// Creates a number from its inner value, the number multiplied by 10^PRECISION
+            ///
+            /// The caller is responsible to calculate the correct inner value
+            pub const fn new(inner: $inner) -> Self {
+                $name {
+                    inner
+                }
+            }
+
+            /// Creates a number with a value of 0
+            pub const fn zero() -> Self {
+                Self::new(0)
+            }
+
+            /// Largest representable number
+            pub const MAX: Self = Self::new(<$inner>::MAX);
+
+            /// Smallest representable number
+            pub const MIN: Self = Self::new(<$inner>::MIN);
+
+            /// Adds two numbers, returns None when the result cannot be represented
+            pub fn checked_add(self, rhs: Self) -> Option<Self> {
+                self.inner.checked_add(rhs.inner).map(Self::new)
+            }
+
+            /// Subtracts two numbers, returns None when the result cannot be represented
+            pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+                self.inner.checked_sub(rhs.inner).map(Self::new)
+            }
+
+            /// Negates the number, returns None when the result cannot be represented
+            pub fn checked_neg(self) -> Option<Self> {
+                self.inner.checked_neg().map(Self::new)
+            }
+
+            fn from_wide(inner: i128) -> Option<Self> {
+                <$inner as std::convert::TryFrom<i128>>::try_from(inner).ok().map(Self::new)
+            }
+
+            /// Multiplies the number by an integer, returns None when the result cannot be represented
+            pub fn checked_mul_int(self, rhs: $inner) -> Option<Self> {
+                self.inner.checked_mul(rhs).map(Self::new)
+            }
+
+            /// Divides the number by an integer, rounding the result according to mode
+            ///
+            /// Returns None when dividing by zero or when the result cannot be represented
+            pub fn checked_div_int(self, rhs: $inner, mode: $crate::fp_isize::RoundingMode) -> Option<Self> {
+                $crate::fp_isize::div_round(self.inner as i128, rhs as i128, mode).and_then(Self::from_wide)
+            }
+
+            /// Multiplies two numbers, rounding the result to PRECISION fractional digits according to mode
+            ///
+            /// Returns None when the result cannot be represented
+            pub fn checked_mul(self, rhs: Self, mode: $crate::fp_isize::RoundingMode) -> Option<Self> {
+                let product = (self.inner as i128).checked_mul(rhs.inner as i128)?;
+
+                $crate::fp_isize::div_round(product, 10i128.checked_pow(PRECISION)?, mode).and_then(Self::from_wide)
+            }
+
+            /// Divides two numbers, rounding the result to PRECISION fractional digits according to mode
+            ///
+            /// Returns None when dividing by zero or when the result cannot be represented
+            pub fn checked_div(self, rhs: Self, mode: $crate::fp_isize::RoundingMode) -> Option<Self> {
+                let numerator = (self.inner as i128).checked_mul(10i128.checked_pow(PRECISION)?)?;
+
+                $crate::fp_isize::div_round(numerator, rhs.inner as i128, mode).and_then(Self::from_wide)
+            }
+
+            /// Rounds the number to the given number of fractional digits according to mode
+            ///
+            /// Returns the number unchanged when digits is not smaller than PRECISION
+            pub fn round(self, digits: u32, mode: $crate::fp_isize::RoundingMode) -> Option<Self> {
+                if digits >= PRECISION {
+                    Some(self)
+                } else {
+                    let factor = 10i128.checked_pow(PRECISION - digits)?;
+
+                    $crate::fp_isize::div_round(self.inner as i128, factor, mode)
+                        .and_then(|rounded| rounded.checked_mul(factor))
+                        .and_then(Self::from_wide)
+                }
+            }
+        }
 
-impl <const PRECISION: u32> Neg for FpIsize<PRECISION> {
-    type Output = Self;
+        impl <const PRECISION: u32> std::ops::Add for $name<PRECISION> {
+            type Output = Self;
 
-    fn neg(self) -> Self::Output {
-        Self::new(-self.inner)
-    }
-}
+            fn add(self, rhs: Self) -> Self::Output {
+                Self::new(self.inner + rhs.inner)
+            }
+        }
 
-impl <const PRECISION: u32> TryFrom<(&str,&str)> for FpIsize<PRECISION> {
-    type Error = &'static str;
+        impl <const PRECISION: u32> std::ops::AddAssign for $name<PRECISION> {
+            fn add_assign(&mut self, rhs: Self) {
+                self.inner += rhs.inner
+            }
+        }
 
-    /// Converts 2 string arguments to a new TxAmount
-    ///
-    /// Expected format of the original string: "integral.fractional"
-    fn try_from((integral, fractional): (&str,&str)) -> Result<Self, Self::Error> {
-        let precision = u32::try_from(fractional.len()).unwrap();
+        impl <const PRECISION: u32> std::ops::Sub for $name<PRECISION> {
+            type Output = Self;
 
-        if precision > PRECISION {
-            Err("Number should not contain more fractional digits than defined in PRECISION")
-        } else {
-            let integral = isize::from_str_radix(integral, 10).unwrap();
-            let fractional = isize::from_str_radix(fractional, 10).unwrap();
+            fn sub(self, rhs: Self) -> Self::Output {
+                Self::new(self.inner - rhs.inner)
+            }
+        }
 
-            Ok(Self::new(integral * 10isize.pow(PRECISION) + fractional * 10isize.pow(PRECISION - precision)))
+        impl <const PRECISION: u32> std::ops::SubAssign for $name<PRECISION> {
+            fn sub_assign(&mut self, rhs: Self) {
+                self.inner -= rhs.inner
+            }
         }
-    }
 
-}
+        impl <const PRECISION: u32> std::ops::Neg for $name<PRECISION> {
+            type Output = Self;
 
-impl <const PRECISION: u32> Debug for FpIsize<PRECISION> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self)
-    }
-}
+            fn neg(self) -> Self::Output {
+                Self::new(-self.inner)
+            }
+        }
 
-impl <const PRECISION: u32> Display for FpIsize<PRECISION> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let integral = (self.inner / precision_factor(PRECISION)).abs();
-        let fractional = (self.inner % precision_factor(PRECISION)).abs();
+        impl <const PRECISION: u32> std::convert::TryFrom<(&str,&str)> for $name<PRECISION> {
+            type Error = $crate::fp_isize::FpParseError;
+
+            /// Converts 2 string arguments to a number
+            ///
+            /// Expected format of the original string: "integral.fractional"
+            /// An empty integral or fractional part is treated as 0
+            fn try_from((integral, fractional): (&str,&str)) -> Result<Self, Self::Error> {
+                Self::from_parts(integral, fractional, false)
+            }
 
-        if self.inner < 0 {
-            write!(f, "-{}.{:0precision$}", integral, fractional, precision = PRECISION as usize)
-        } else {
-            write!(f, "{}.{:0precision$}", integral, fractional, precision = PRECISION as usize)
         }
-    }
+
+        impl <const PRECISION: u32> std::str::FromStr for $name<PRECISION> {
+            type Err = $crate::fp_isize::FpParseError;
+
+            /// Parses a decimal number with an optional sign and an optional fractional part
+            ///
+            /// Accepts e.g. "1.5", "-1.5", "+1", "1." and ".5"
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let (negative, number) = match s.as_bytes().first() {
+                    Some(b'-') => (true, &s[1..]),
+                    Some(b'+') => (false, &s[1..]),
+                    _ => (false, s),
+                };
+
+                let (integral, fractional) = number.split_once('.').unwrap_or((number, ""));
+
+                if integral.is_empty() && fractional.is_empty() {
+                    Err($crate::fp_isize::FpParseError::InvalidDigit)
+                } else {
+                    Self::from_parts(integral, fractional, negative)
+                }
+            }
+        }
+
+        impl <const PRECISION: u32> std::iter::Sum for $name<PRECISION> {
+            /// Sums the numbers, panics like `Add` when the sum cannot be represented
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.fold(Self::zero(), |sum, number| sum + number)
+            }
+        }
+
+        impl <'a, const PRECISION: u32> std::iter::Sum<&'a $name<PRECISION>> for $name<PRECISION> {
+            /// Sums the numbers, panics like `Add` when the sum cannot be represented
+            fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+                iter.copied().sum()
+            }
+        }
+
+        /// Serializes the number as a decimal string, e.g. "1.5000"
+        #[cfg(feature = "serde")]
+        impl <const PRECISION: u32> serde::Serialize for $name<PRECISION> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        /// Deserializes the number from a decimal string, see `FromStr`
+        #[cfg(feature = "serde")]
+        impl <'de, const PRECISION: u32> serde::Deserialize<'de> for $name<PRECISION> {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                struct Visitor<const PRECISION: u32>;
+
+                impl <'de, const PRECISION: u32> serde::de::Visitor<'de> for Visitor<PRECISION> {
+                    type Value = $name<PRECISION>;
+
+                    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        write!(f, "a decimal number with at most {} fractional digits as a string", PRECISION)
+                    }
+
+                    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                        value.parse().map_err(E::custom)
+                    }
+                }
+
+                deserializer.deserialize_str(Visitor::<PRECISION>)
+            }
+        }
+
+        impl <const PRECISION: u32> $name<PRECISION> {
+
+            /// Converts the integral and fractional digits to a number, negated when negative is set
+            fn from_parts(integral: &str, fractional: &str, negative: bool) -> Result<Self, $crate::fp_isize::FpParseError> {
+                let precision = <u32 as std::convert::TryFrom<usize>>::try_from(fractional.len()).map_err(|_| $crate::fp_isize::FpParseError::ExcessPrecision)?;
+
+                if precision > PRECISION {
+                    Err($crate::fp_isize::FpParseError::ExcessPrecision)
+                } else {
+                    let integral = $crate::fp_isize::parse_digits::<$inner>(integral)?;
+                    let fractional = $crate::fp_isize::parse_digits::<$inner>(fractional)?;
+
+                    let integral = (10 as $inner).checked_pow(PRECISION).and_then(|factor| integral.checked_mul(factor));
+                    let fractional = (10 as $inner).checked_pow(PRECISION - precision).and_then(|factor| fractional.checked_mul(factor));
+
+                    integral.zip(fractional)
+                        .and_then(|(integral, fractional)| if negative {
+                            integral.checked_neg().and_then(|integral| integral.checked_sub(fractional))
+                        } else {
+                            integral.checked_add(fractional)
+                        })
+                        .map(Self::new)
+                        .ok_or($crate::fp_isize::FpParseError::Overflow)
+                }
+            }
+
+            /// Converts 2 string arguments to a number, rounding excess fractional digits according to mode
+            ///
+            /// Unlike `try_from` the fractional part may contain more than PRECISION digits.
+            /// Returns the number and whether it had to be rounded, excess digits which are all 0 do not require rounding.
+            pub fn try_from_rounded((integral, fractional): (&str,&str), mode: $crate::fp_isize::RoundingMode) -> Result<(Self, bool), $crate::fp_isize::FpParseError> {
+                if !fractional.bytes().all(|digit| digit.is_ascii_digit()) {
+                    return Err($crate::fp_isize::FpParseError::InvalidDigit);
+                }
+
+                let (fractional, excess) = fractional.split_at(fractional.len().min(PRECISION as usize));
+                let number = <Self as std::convert::TryFrom<(&str, &str)>>::try_from((integral, fractional))?;
+
+                let mut excess = excess.bytes().map(|digit| digit - b'0');
+
+                let first = match excess.next() {
+                    Some(first) => first,
+                    None => return Ok((number, false)),
+                };
+
+                let rest = excess.any(|digit| digit != 0);
+
+                if first == 0 && !rest {
+                    return Ok((number, false));
+                }
+
+                // The parsed digits are never negative, so the excess always points away from zero
+                let away_from_zero = match mode {
+                    $crate::fp_isize::RoundingMode::HalfEven => first > 5 || (first == 5 && (rest || number.inner % 2 != 0)),
+                    $crate::fp_isize::RoundingMode::HalfUp => first >= 5,
+                    $crate::fp_isize::RoundingMode::TowardZero | $crate::fp_isize::RoundingMode::Floor => false,
+                    $crate::fp_isize::RoundingMode::Ceil => true,
+                };
+
+                if away_from_zero {
+                    number.checked_add(Self::new(1)).map(|number| (number, true)).ok_or($crate::fp_isize::FpParseError::Overflow)
+                } else {
+                    Ok((number, true))
+                }
+            }
+        }
+
+        impl <const PRECISION: u32> std::fmt::Debug for $name<PRECISION> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self)
+            }
+        }
+
+        impl <const PRECISION: u32> std::fmt::Display for $name<PRECISION> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let integral = (self.inner / (10 as $inner).pow(PRECISION)).abs();
+                let fractional = (self.inner % (10 as $inner).pow(PRECISION)).abs();
+
+                if self.inner < 0 {
+                    write!(f, "-{}.{:0precision$}", integral, fractional, precision = PRECISION as usize)
+                } else {
+                    write!(f, "{}.{:0precision$}", integral, fractional, precision = PRECISION as usize)
+                }
+            }
+        }
+    };
 }
 
+pub(crate) use fixed_point;
+
+fixed_point!(FpIsize, isize, "Can maximally represent 64 Bit values, 32 Bit values on 32 Bit targets");
+
 #[cfg(test)]
 mod test {
     use std::convert::TryFrom;
 
-    use super::FpIsize;
+    use super::{FpIsize, FpParseError, RoundingMode};
+
+    type Amount = FpIsize<4>;
+
+    const MODES: [RoundingMode; 5] = [
+        RoundingMode::HalfEven,
+        RoundingMode::HalfUp,
+        RoundingMode::TowardZero,
+        RoundingMode::Floor,
+        RoundingMode::Ceil,
+    ];
+
+    fn for_each_mode(op: impl Fn(RoundingMode) -> Option<FpIsize<1>>) -> Vec<Option<FpIsize<1>>> {
+        MODES.iter().map(|mode| op(*mode)).collect()
+    }
 
 
     #[test]
@@ -128,11 +447,11 @@ mod test {
     fn parse() {
         let number = FpIsize::<0>::try_from(("10", "15"));
 
-        assert_eq!(number, Err("Number should not contain more fractional digits than defined in PRECISION"));
+        assert_eq!(number, Err(FpParseError::ExcessPrecision));
 
         let number = FpIsize::<1>::try_from(("10", "15"));
 
-        assert_eq!(number, Err("Number should not contain more fractional digits than defined in PRECISION"));
+        assert_eq!(number, Err(FpParseError::ExcessPrecision));
 
         let number = FpIsize::<2>::try_from(("10", "15"));
 
@@ -145,6 +464,14 @@ mod test {
         let number = FpIsize::<4>::try_from(("0", "15"));
 
         assert_eq!(number, Ok(FpIsize::new(1500)));
+
+        let number = FpIsize::<4>::try_from(("", "5"));
+
+        assert_eq!(number, Ok(FpIsize::new(5000)));
+
+        let number = FpIsize::<4>::try_from(("5", ""));
+
+        assert_eq!(number, Ok(FpIsize::new(50000)));
     }
 
     #[test]
@@ -158,4 +485,235 @@ mod test {
         assert_eq!(format!("{}", number), "0.1010");
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn parse_overflow() {
+        let number = FpIsize::<4>::try_from(("99999999999999999999999", "0"));
+
+        assert_eq!(number, Err(FpParseError::Overflow));
+
+        let max = isize::MAX.to_string();
+        let (integral, fractional) = max.split_at(max.len() - 4);
+
+        let number = FpIsize::<4>::try_from((integral, fractional));
+
+        assert_eq!(number, Ok(FpIsize::new(isize::MAX)));
+
+        let number = FpIsize::<4>::try_from((integral, "9999"));
+
+        assert_eq!(number, Err(FpParseError::Overflow));
+
+        let number = FpIsize::<4>::try_from((max.as_str(), "0"));
+
+        assert_eq!(number, Err(FpParseError::Overflow));
+
+        let number = FpIsize::<4>::try_from(("1", "x"));
+
+        assert_eq!(number, Err(FpParseError::InvalidDigit));
+
+        let number = FpIsize::<4>::try_from(("-1", "0"));
+
+        assert_eq!(number, Err(FpParseError::InvalidDigit));
+    }
+
+    #[test]
+    fn checked() {
+        let max = FpIsize::<4>::new(isize::MAX);
+        let min = FpIsize::<4>::new(isize::MIN);
+        let one = FpIsize::<4>::new(1);
+
+        assert_eq!(one.checked_add(one), Some(FpIsize::new(2)));
+        assert_eq!(max.checked_add(one), None);
+
+        assert_eq!(one.checked_sub(one), Some(FpIsize::zero()));
+        assert_eq!(min.checked_sub(one), None);
+
+        assert_eq!(one.checked_neg(), Some(FpIsize::new(-1)));
+        assert_eq!(min.checked_neg(), None);
+    }
+
+    #[test]
+    fn mul_int() {
+        assert_eq!(FpIsize::<4>::new(15000).checked_mul_int(3), Some(FpIsize::new(45000)));
+        assert_eq!(FpIsize::<4>::new(15000).checked_mul_int(-2), Some(FpIsize::new(-30000)));
+        assert_eq!(FpIsize::<4>::new(isize::MAX).checked_mul_int(2), None);
+    }
+
+    #[test]
+    fn div_int() {
+        let number = FpIsize::<1>::new(25);
+
+        // 2.5 / 2 = 1.25
+        assert_eq!(
+            for_each_mode(|mode| number.checked_div_int(2, mode)),
+            [Some(FpIsize::new(12)), Some(FpIsize::new(13)), Some(FpIsize::new(12)), Some(FpIsize::new(12)), Some(FpIsize::new(13))],
+        );
+
+        // -2.5 / 2 = -1.25
+        assert_eq!(
+            for_each_mode(|mode| (-number).checked_div_int(2, mode)),
+            [Some(FpIsize::new(-12)), Some(FpIsize::new(-13)), Some(FpIsize::new(-12)), Some(FpIsize::new(-13)), Some(FpIsize::new(-12))],
+        );
+
+        // 3.5 / 2 = 1.75
+        assert_eq!(
+            for_each_mode(|mode| FpIsize::<1>::new(35).checked_div_int(2, mode)),
+            [Some(FpIsize::new(18)), Some(FpIsize::new(18)), Some(FpIsize::new(17)), Some(FpIsize::new(17)), Some(FpIsize::new(18))],
+        );
+
+        // 1.0 / 3 = 0.333..
+        assert_eq!(
+            for_each_mode(|mode| FpIsize::<1>::new(10).checked_div_int(3, mode)),
+            [Some(FpIsize::new(3)), Some(FpIsize::new(3)), Some(FpIsize::new(3)), Some(FpIsize::new(3)), Some(FpIsize::new(4))],
+        );
+
+        // 2.0 / -3 = -0.666..
+        assert_eq!(
+            for_each_mode(|mode| FpIsize::<1>::new(20).checked_div_int(-3, mode)),
+            [Some(FpIsize::new(-7)), Some(FpIsize::new(-7)), Some(FpIsize::new(-6)), Some(FpIsize::new(-7)), Some(FpIsize::new(-6))],
+        );
+
+        assert_eq!(for_each_mode(|mode| number.checked_div_int(0, mode)), [None; 5]);
+        assert_eq!(FpIsize::<1>::new(isize::MIN).checked_div_int(-1, RoundingMode::HalfEven), None);
+    }
+
+    #[test]
+    fn mul() {
+        // 2.5 * 0.5 = 1.25
+        assert_eq!(
+            for_each_mode(|mode| FpIsize::<1>::new(25).checked_mul(FpIsize::new(5), mode)),
+            [Some(FpIsize::new(12)), Some(FpIsize::new(13)), Some(FpIsize::new(12)), Some(FpIsize::new(12)), Some(FpIsize::new(13))],
+        );
+
+        // 2.5 * -0.5 = -1.25
+        assert_eq!(
+            for_each_mode(|mode| FpIsize::<1>::new(25).checked_mul(FpIsize::new(-5), mode)),
+            [Some(FpIsize::new(-12)), Some(FpIsize::new(-13)), Some(FpIsize::new(-12)), Some(FpIsize::new(-13)), Some(FpIsize::new(-12))],
+        );
+
+        // 1.5 * 1.5 = 2.25, exact at a precision of 4
+        assert_eq!(FpIsize::<4>::new(15000).checked_mul(FpIsize::new(15000), RoundingMode::Floor), Some(FpIsize::new(22500)));
+
+        // Intermediate results exceeding isize are fine as long as the result fits
+        assert_eq!(FpIsize::<4>::new(isize::MAX).checked_mul(FpIsize::new(10000), RoundingMode::HalfEven), Some(FpIsize::new(isize::MAX)));
+        assert_eq!(FpIsize::<4>::new(isize::MAX).checked_mul(FpIsize::new(20000), RoundingMode::HalfEven), None);
+    }
+
+    #[test]
+    fn div() {
+        // 1.0 / 0.4 = 2.5, exact
+        assert_eq!(FpIsize::<1>::new(10).checked_div(FpIsize::new(4), RoundingMode::TowardZero), Some(FpIsize::new(25)));
+
+        // 0.5 / 0.4 = 1.25
+        assert_eq!(
+            for_each_mode(|mode| FpIsize::<1>::new(5).checked_div(FpIsize::new(4), mode)),
+            [Some(FpIsize::new(12)), Some(FpIsize::new(13)), Some(FpIsize::new(12)), Some(FpIsize::new(12)), Some(FpIsize::new(13))],
+        );
+
+        // -0.5 / 0.4 = -1.25
+        assert_eq!(
+            for_each_mode(|mode| FpIsize::<1>::new(-5).checked_div(FpIsize::new(4), mode)),
+            [Some(FpIsize::new(-12)), Some(FpIsize::new(-13)), Some(FpIsize::new(-12)), Some(FpIsize::new(-13)), Some(FpIsize::new(-12))],
+        );
+
+        assert_eq!(FpIsize::<1>::new(5).checked_div(FpIsize::zero(), RoundingMode::HalfUp), None);
+        assert_eq!(FpIsize::<4>::new(isize::MAX).checked_div(FpIsize::new(1), RoundingMode::HalfUp), None);
+    }
+
+    #[test]
+    fn round() {
+        let number = FpIsize::<4>::new(12_345);
+
+        assert_eq!(number.round(3, RoundingMode::HalfEven), Some(FpIsize::new(12_340)));
+        assert_eq!(number.round(3, RoundingMode::HalfUp), Some(FpIsize::new(12_350)));
+        assert_eq!(number.round(3, RoundingMode::TowardZero), Some(FpIsize::new(12_340)));
+        assert_eq!((-number).round(3, RoundingMode::Floor), Some(FpIsize::new(-12_350)));
+        assert_eq!((-number).round(3, RoundingMode::Ceil), Some(FpIsize::new(-12_340)));
+        assert_eq!(number.round(4, RoundingMode::Ceil), Some(number));
+    }
+
+    #[test]
+    fn parse_rounded() {
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "2"), RoundingMode::Ceil), Ok((FpIsize::new(120), false)));
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "2300"), RoundingMode::Ceil), Ok((FpIsize::new(123), false)));
+
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "235"), RoundingMode::HalfEven), Ok((FpIsize::new(124), true)));
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "245"), RoundingMode::HalfEven), Ok((FpIsize::new(124), true)));
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "2451"), RoundingMode::HalfEven), Ok((FpIsize::new(125), true)));
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "245"), RoundingMode::HalfUp), Ok((FpIsize::new(125), true)));
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "2449"), RoundingMode::HalfUp), Ok((FpIsize::new(124), true)));
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "2499"), RoundingMode::TowardZero), Ok((FpIsize::new(124), true)));
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "2499"), RoundingMode::Floor), Ok((FpIsize::new(124), true)));
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "2401"), RoundingMode::Ceil), Ok((FpIsize::new(125), true)));
+
+        assert_eq!(FpIsize::<2>::try_from_rounded(("1", "2x1"), RoundingMode::Ceil), Err(FpParseError::InvalidDigit));
+        assert_eq!(FpIsize::<2>::try_from_rounded(("", "999"), RoundingMode::Ceil), Ok((FpIsize::new(100), true)));
+
+        let max = isize::MAX.to_string();
+        let (integral, fractional) = max.split_at(max.len() - 2);
+
+        assert_eq!(
+            FpIsize::<2>::try_from_rounded((integral, &format!("{}1", fractional)), RoundingMode::Ceil),
+            Err(FpParseError::Overflow),
+        );
+    }
+
+    #[test]
+    fn rounding_mode() {
+        assert_eq!("half-even".parse(), Ok(RoundingMode::HalfEven));
+        assert_eq!("ceil".parse(), Ok(RoundingMode::Ceil));
+        assert!("nearest".parse::<RoundingMode>().is_err());
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!("1.5".parse(), Ok(Amount::new(15000)));
+        assert_eq!("+1.5".parse(), Ok(Amount::new(15000)));
+        assert_eq!("-1.5".parse(), Ok(Amount::new(-15000)));
+        assert_eq!("-0.0001".parse(), Ok(Amount::new(-1)));
+        assert_eq!("7".parse(), Ok(Amount::new(70000)));
+        assert_eq!("7.".parse(), Ok(Amount::new(70000)));
+        assert_eq!(".5".parse(), Ok(Amount::new(5000)));
+        assert_eq!("-.5".parse(), Ok(Amount::new(-5000)));
+
+        assert_eq!("1.23456".parse::<Amount>(), Err(FpParseError::ExcessPrecision));
+        assert_eq!("".parse::<Amount>(), Err(FpParseError::InvalidDigit));
+        assert_eq!(".".parse::<Amount>(), Err(FpParseError::InvalidDigit));
+        assert_eq!("-".parse::<Amount>(), Err(FpParseError::InvalidDigit));
+        assert_eq!("--1".parse::<Amount>(), Err(FpParseError::InvalidDigit));
+        assert_eq!("1.2.3".parse::<Amount>(), Err(FpParseError::InvalidDigit));
+        assert_eq!(" 1".parse::<Amount>(), Err(FpParseError::InvalidDigit));
+
+        for number in [Amount::MAX, Amount::MIN, Amount::zero(), Amount::new(-1), Amount::new(123456789)] {
+            assert_eq!(number.to_string().parse(), Ok(number));
+        }
+    }
+
+    #[test]
+    fn sum() {
+        let numbers = [Amount::new(15000), Amount::new(-5000), Amount::new(1)];
+
+        assert_eq!(numbers.iter().sum::<Amount>(), Amount::new(10001));
+        assert_eq!(numbers.iter().copied().sum::<Amount>(), Amount::new(10001));
+        assert_eq!(std::iter::empty::<Amount>().sum::<Amount>(), Amount::zero());
+    }
+
+    #[test]
+    fn default() {
+        assert_eq!(Amount::default(), Amount::zero());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let number = Amount::new(-15);
+
+        assert_eq!(serde_json::to_string(&number).unwrap(), "\"-0.0015\"");
+        assert_eq!(serde_json::from_str::<Amount>("\"-0.0015\"").unwrap(), number);
+
+        assert_eq!(serde_json::from_str::<Amount>(&serde_json::to_string(&Amount::MIN).unwrap()).unwrap(), Amount::MIN);
+
+        assert!(serde_json::from_str::<Amount>("\"1.23456\"").is_err());
+        assert!(serde_json::from_str::<Amount>("1.5").is_err());
+    }
+
+}
@@ -0,0 +1,297 @@
+use std::{fs, io, path::{Path, PathBuf}, str::FromStr};
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+
+/// Input argument standing for the standard input
+pub const STDIN: &str = "-";
+
+/// Expands the input arguments into the files to process, in the order of the arguments
+///
+/// A directory stands for all files in it, a file name containing `*` or `?` for all matching files
+/// in its directory. The files of a directory or pattern are sorted by name, hidden files are skipped.
+/// `-` is passed through and read from stdin by `open`.
+pub fn expand(args: &[String]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for arg in args {
+        let path = Path::new(arg);
+
+        if arg == STDIN {
+            files.push(path.to_path_buf());
+
+            continue;
+        }
+
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+
+        if name.contains(['*', '?'].as_ref()) {
+            let directory = match path.parent() {
+                Some(parent) if parent != Path::new("") => parent,
+                _ => Path::new("."),
+            };
+
+            let matching = list(directory, |file| matches(name, file))?;
+
+            if matching.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("No input file matches {}", arg)));
+            }
+
+            files.extend(matching);
+        } else if path.is_dir() {
+            files.extend(list(path, |_| true)?);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    Ok(files)
+}
+
+/// Format of the rows of an input
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Format {
+    /// CSV with a header naming the columns
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl Format {
+    /// Format according to the file extension, ignoring a compression extension
+    ///
+    /// `.jsonl` and `.ndjson` files are JSON Lines, all other files CSV
+    pub fn from_extension(path: &Path) -> Self {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let name = name.strip_suffix(".gz").or_else(|| name.strip_suffix(".zst")).unwrap_or(name);
+
+        if name.ends_with(".jsonl") || name.ends_with(".ndjson") {
+            Format::JsonLines
+        } else {
+            Format::Csv
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::JsonLines),
+            _ => Err("Unknown input format, expected csv or jsonl"),
+        }
+    }
+}
+
+/// Compression of an input, detected from the first bytes
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &'static [u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+    fn detect(prefix: &[u8]) -> Self {
+        if prefix.starts_with(Self::GZIP_MAGIC) {
+            Compression::Gzip
+        } else if prefix.starts_with(Self::ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Opens an input file, `-` opens stdin
+///
+/// Gzip and zstd compressed inputs are decompressed while reading
+pub async fn open(path: &Path) -> io::Result<Box<dyn AsyncRead + Unpin + Send>> {
+    let mut reader: Box<dyn AsyncRead + Unpin + Send> = if path == Path::new(STDIN) {
+        Box::new(tokio::io::stdin())
+    } else {
+        Box::new(tokio::fs::File::open(path).await?)
+    };
+
+    // A single read can return less than the magic bytes, e.g. from a pipe
+    let mut prefix = Vec::with_capacity(Compression::ZSTD_MAGIC.len());
+
+    while prefix.len() < Compression::ZSTD_MAGIC.len() {
+        let mut byte = [0];
+
+        if reader.read(&mut byte).await? == 0 {
+            break;
+        }
+
+        prefix.push(byte[0]);
+    }
+
+    let compression = Compression::detect(&prefix);
+    let reader = BufReader::new(io::Cursor::new(prefix).chain(reader));
+
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => {
+            let mut decoder = GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+
+            Box::new(decoder)
+        },
+        Compression::Zstd => {
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+
+            Box::new(decoder)
+        },
+    })
+}
+
+/// Files in the directory accepted by the filter, sorted by name
+fn list(directory: &Path, filter: impl Fn(&str) -> bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        if !name.starts_with('.') && filter(&name) && entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Matches a file name against a pattern, `*` matches any number of characters and `?` a single character
+fn matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    // Position after the last `*` in the pattern and the position in the name it was matched up to
+    let mut backtrack = None;
+
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                // Let the last `*` match one more character
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star;
+                    n = matched + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::{Path, PathBuf}};
+
+    use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+    use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    use super::{Compression, Format, expand, matches, open};
+
+    #[test]
+    fn pattern() {
+        assert!(matches("*.csv", "2021-10-01.csv"));
+        assert!(matches("2021-10-0?.csv", "2021-10-01.csv"));
+        assert!(matches("*-*-01*", "2021-10-01.csv"));
+        assert!(matches("*", ""));
+
+        assert!(!matches("*.csv", "2021-10-01.csv.gz"));
+        assert!(!matches("2021-10-0?.csv", "2021-10-10.csv"));
+        assert!(!matches("?", ""));
+    }
+
+    #[test]
+    fn expansion() {
+        let directory = std::env::temp_dir().join(format!("transaction-processor-input-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        fs::create_dir_all(directory.join("nested")).unwrap();
+
+        for name in ["b.csv", "a.csv", "c.txt", ".hidden.csv"].iter() {
+            fs::write(directory.join(name), "").unwrap();
+        }
+
+        let path = |name: &str| directory.join(name).to_string_lossy().into_owned();
+
+        assert_eq!(expand(&[path("")]).unwrap(), [directory.join("a.csv"), directory.join("b.csv"), directory.join("c.txt")]);
+        assert_eq!(expand(&[path("c.txt"), path("*.csv")]).unwrap(), [directory.join("c.txt"), directory.join("a.csv"), directory.join("b.csv")]);
+        assert!(expand(&[path("*.json")]).is_err());
+
+        assert_eq!(expand(&["-".to_string(), path("a.csv")]).unwrap(), [PathBuf::from("-"), directory.join("a.csv")]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(Format::from_extension(Path::new("daily/2021-10-01.csv")), Format::Csv);
+        assert_eq!(Format::from_extension(Path::new("daily/2021-10-01.jsonl")), Format::JsonLines);
+        assert_eq!(Format::from_extension(Path::new("events.ndjson.zst")), Format::JsonLines);
+        assert_eq!(Format::from_extension(Path::new("-")), Format::Csv);
+
+        assert_eq!("jsonl".parse(), Ok(Format::JsonLines));
+        assert!("json".parse::<Format>().is_err());
+    }
+
+    async fn compress<W: AsyncWrite + Unpin>(mut encoder: W, data: &[u8]) -> W {
+        encoder.write_all(data).await.unwrap();
+        encoder.shutdown().await.unwrap();
+
+        encoder
+    }
+
+    #[tokio::test]
+    async fn compression() {
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 0x08, 0x00]), Compression::Gzip);
+        assert_eq!(Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd]), Compression::Zstd);
+        assert_eq!(Compression::detect(b"type"), Compression::None);
+        assert_eq!(Compression::detect(b""), Compression::None);
+
+        let data = b"type, client, tx, amount\ndeposit, 1, 1, 1.0\n";
+
+        let gzip = compress(GzipEncoder::new(Vec::new()), data).await.into_inner();
+        let zstd = compress(ZstdEncoder::new(Vec::new()), data).await.into_inner();
+
+        let path = std::env::temp_dir().join(format!("transaction-processor-compressed-{}", std::process::id()));
+
+        // Inputs shorter than the magic bytes are passed through as well
+        for (content, expected) in [(&data[..], &data[..]), (&data[..2], &data[..2]), (&gzip, &data[..]), (&zstd, &data[..])].iter() {
+            fs::write(&path, content).unwrap();
+
+            let mut decompressed = Vec::new();
+
+            open(&path).await.unwrap().read_to_end(&mut decompressed).await.unwrap();
+
+            assert_eq!(&decompressed, expected);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
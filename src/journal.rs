@@ -0,0 +1,362 @@
+//! Write-ahead journal of the processed ledger items
+//!
+//! Every shard of the `AccountManagerLoadbalancer` appends the items it processed to its own file
+//! `shard-<index>.journal` in the journal directory, one line per item. Accepted items are written
+//! in full, refused items only with their sequence number:
+//!
+//! ```text
+//! <sequence number>, <type>, <client>, <tx>, <amount>
+//! <sequence number>
+//! ```
+//!
+//! The sequence number is assigned by the loadbalancer in the order the items are submitted.
+//! After a crash the shards replay their journal to rebuild their accounts and skip every item
+//! with a sequence number they already journaled, so the same input can simply be submitted again
+//! without reporting the refused items twice.
+//! A snapshot taken during the run records the last sequence number of every shard as its `JournalPosition`,
+//! restoring it only replays the entries after that position.
+//! Once a run is complete the journal is removed with `Journal::clear`.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{LedgerItem, policy::Policy, processor::{Columns, format_line, parse_line}};
+
+const META_FILE: &str = "journal.meta";
+
+/// Sequence number and item of a journal entry, refused items have no item
+pub type Entry = (u64, Option<LedgerItem>);
+
+/// Items of a journal contained in a snapshot
+///
+/// The run identifies the journal, the sequence numbers are the last ones processed by every shard
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct JournalPosition {
+    pub run:    u64,
+    pub seqs:   Vec<u64>,
+}
+
+/// When the journal is flushed to the disk
+///
+/// Entries are always written to the operating system immediately, so they survive a crash of the process.
+/// The policy controls how many entries can be lost when the whole system crashes.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FsyncPolicy {
+    /// Sync after every entry
+    Always,
+    /// Sync after the given number of entries
+    Every(usize),
+    /// Leave syncing to the operating system
+    Never,
+}
+
+impl Default for FsyncPolicy {
+    fn default() -> Self {
+        FsyncPolicy::Every(1000)
+    }
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = &'static str;
+
+    /// Parses "always", "never" or "every:<entries>"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(FsyncPolicy::Always),
+            "never" => Ok(FsyncPolicy::Never),
+            _ => match s.strip_prefix("every:").map(str::parse) {
+                Some(Ok(0)) => Err("The number of entries between syncs must be larger than 0"),
+                Some(Ok(entries)) => Ok(FsyncPolicy::Every(entries)),
+                _ => Err("Unknown fsync policy, expected always, never or every:<entries>"),
+            },
+        }
+    }
+}
+
+/// Location and fsync policy of the journal
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct JournalConfig {
+    pub directory:  PathBuf,
+    pub fsync:      FsyncPolicy,
+}
+
+/// Journal of a single shard
+pub struct Journal {
+    file:       File,
+    fsync:      FsyncPolicy,
+
+    unsynced:   usize,
+}
+
+impl Journal {
+
+    fn shard_path(directory: &Path, shard: usize) -> PathBuf {
+        directory.join(format!("shard-{}.journal", shard))
+    }
+
+    /// Checks that an existing journal in the directory was written with the same number of shards and dispute policy
+    /// and returns the run which wrote it
+    ///
+    /// Creates the directory and records the number of shards, the policy and a new run when there is no journal yet.
+    /// The maximum number of dispute cycles is only recorded for information, it can change between runs.
+    pub fn prepare(directory: &Path, shards: usize, policy: Policy) -> io::Result<u64> {
+        fs::create_dir_all(directory)?;
+
+        let meta = directory.join(META_FILE);
+        let expected = format!("shards={}\ndispute-policy={}", shards, policy.dispute);
+
+        let settings = |content: &str| content.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("max-dispute-cycles=") && !line.starts_with("run="))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // Journals written before runs were recorded are all taken as run 0
+        let run = |content: &str| content.lines()
+            .find_map(|line| line.trim().strip_prefix("run="))
+            .and_then(|run| run.parse().ok())
+            .unwrap_or(0);
+
+        match fs::read_to_string(&meta) {
+            Ok(content) if settings(&content) == expected => Ok(run(&content)),
+            Ok(content) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Journal in {} was written with a different number of shards or policy ({})",
+                    directory.display(), content.trim().replace('\n', ", "),
+                ),
+            )),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let run = SystemTime::now().duration_since(UNIX_EPOCH).map_err(io::Error::other)?.as_nanos() as u64;
+
+                fs::write(&meta, format!("{}\nmax-dispute-cycles={}\nrun={}", expected, policy.max_dispute_cycles, run))?;
+
+                Ok(run)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the directory holds the journal of an incomplete run, which is replayed by the next one
+    pub fn exists(directory: &Path) -> bool {
+        directory.join(META_FILE).exists()
+    }
+
+    /// Opens the journal of a shard and returns the entries written before
+    ///
+    /// An incomplete or unreadable entry at the end of the journal (e.g. from a crash during a write)
+    /// is cut off together with everything after it
+    pub fn open(config: &JournalConfig, shard: usize) -> io::Result<(Self, Vec<Entry>)> {
+        let path = Self::shard_path(&config.directory, shard);
+
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+
+        let mut entries = Vec::new();
+        let mut valid = 0;
+
+        let mut reader = BufReader::new(&file);
+        let mut line = Vec::new();
+
+        // Only entries terminated by a newline were written completely
+        while reader.read_until(b'\n', &mut line)? > 0 && line.ends_with(b"\n") {
+            match std::str::from_utf8(&line[..line.len() - 1]).ok().and_then(parse_entry) {
+                Some(entry) => {
+                    entries.push(entry);
+                    valid += line.len() as u64;
+                },
+                None => break,
+            }
+
+            line.clear();
+        }
+
+        if valid < file.metadata()?.len() {
+            eprintln!("Discarding incomplete entries at the end of {}", path.display());
+
+            file.set_len(valid)?;
+            file.sync_data()?;
+        }
+
+        // Writes happen at the end of the file, independent of the read position
+        file = OpenOptions::new().append(true).open(&path)?;
+
+        let journal = Journal {
+            file,
+            fsync:      config.fsync,
+
+            unsynced:   0,
+        };
+
+        Ok((journal, entries))
+    }
+
+    /// Appends an accepted item
+    pub fn append(&mut self, seq: u64, item: &LedgerItem) -> io::Result<()> {
+        self.write(format_entry(seq, item))
+    }
+
+    /// Appends the sequence number of a refused item
+    pub fn append_refused(&mut self, seq: u64) -> io::Result<()> {
+        self.write(format!("{}\n", seq))
+    }
+
+    fn write(&mut self, entry: String) -> io::Result<()> {
+        self.file.write_all(entry.as_bytes())?;
+
+        self.unsynced += 1;
+
+        match self.fsync {
+            FsyncPolicy::Always => self.sync(),
+            FsyncPolicy::Every(entries) if self.unsynced >= entries => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Flushes all entries to the disk
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.unsynced = 0;
+
+        self.file.sync_data()
+    }
+
+    /// Removes the journal of all shards from the directory
+    pub fn clear(directory: &Path) -> io::Result<()> {
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+
+            let is_journal = path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name == META_FILE || (name.starts_with("shard-") && name.ends_with(".journal")));
+
+            if is_journal {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn format_entry(seq: u64, item: &LedgerItem) -> String {
+    format!("{}, {}\n", seq, format_line(item))
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let (seq, row) = match line.split_once(',') {
+        Some((seq, row)) => (seq, Some(row)),
+        None => (line, None),
+    };
+
+    let seq = seq.parse().ok()?;
+
+    match row {
+        Some(row) => Some((seq, Some(parse_line(&Columns::default(), row).ok()?))),
+        None => Some((seq, None)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::Write, path::PathBuf};
+
+    use crate::{LedgerAction, LedgerItem, TxAmount, policy::{DisputePolicy, Policy}};
+
+    use super::{FsyncPolicy, Journal, JournalConfig};
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("transaction-processor-journal-{}-{}", name, std::process::id()));
+
+        let _ = fs::remove_dir_all(&directory);
+
+        directory
+    }
+
+    fn deposit(tx_id: u32) -> LedgerItem {
+        LedgerItem {
+            client_id:  1,
+            tx_id,
+            action:     LedgerAction::Deposit(TxAmount::new(10000)),
+        }
+    }
+
+    #[test]
+    fn fsync_policy() {
+        assert_eq!("always".parse(), Ok(FsyncPolicy::Always));
+        assert_eq!("never".parse(), Ok(FsyncPolicy::Never));
+        assert_eq!("every:10".parse(), Ok(FsyncPolicy::Every(10)));
+        assert!("every:0".parse::<FsyncPolicy>().is_err());
+        assert!("sometimes".parse::<FsyncPolicy>().is_err());
+    }
+
+    #[test]
+    fn replay() {
+        let config = JournalConfig { directory: directory("replay"), fsync: FsyncPolicy::Always };
+
+        let run = Journal::prepare(&config.directory, 2, Policy::default()).unwrap();
+
+        let (mut journal, entries) = Journal::open(&config, 1).unwrap();
+
+        assert!(entries.is_empty());
+
+        journal.append(3, &deposit(1)).unwrap();
+        journal.append_refused(5).unwrap();
+        journal.append(7, &LedgerItem { client_id: 1, tx_id: 1, action: LedgerAction::Dispute(None) }).unwrap();
+
+        drop(journal);
+
+        let (_, entries) = Journal::open(&config, 1).unwrap();
+
+        let entries = entries.iter().map(|(seq, item)| (*seq, item.map(|item| (item.tx_id, item.action)))).collect::<Vec<_>>();
+
+        assert_eq!(entries, [
+            (3, Some((1, LedgerAction::Deposit(TxAmount::new(10000))))),
+            (5, None),
+            (7, Some((1, LedgerAction::Dispute(None)))),
+        ]);
+
+        assert_eq!(Journal::prepare(&config.directory, 2, Policy::default()).unwrap(), run);
+        assert!(Journal::prepare(&config.directory, 4, Policy::default()).is_err());
+        assert!(Journal::prepare(&config.directory, 2, Policy { dispute: DisputePolicy::DenyWithdrawals, ..Policy::default() }).is_err());
+        assert!(Journal::prepare(&config.directory, 2, Policy { max_dispute_cycles: 2, ..Policy::default() }).is_ok());
+
+        Journal::clear(&config.directory).unwrap();
+
+        assert_eq!(fs::read_dir(&config.directory).unwrap().count(), 0);
+        assert_ne!(Journal::prepare(&config.directory, 4, Policy::default()).unwrap(), run);
+
+        fs::remove_dir_all(&config.directory).unwrap();
+    }
+
+    #[test]
+    fn incomplete_entry() {
+        let config = JournalConfig { directory: directory("incomplete"), fsync: FsyncPolicy::Never };
+
+        Journal::prepare(&config.directory, 1, Policy::default()).unwrap();
+
+        let (mut journal, _) = Journal::open(&config, 0).unwrap();
+
+        journal.append(1, &deposit(1)).unwrap();
+        journal.file.write_all(b"2, deposit, 1, 2, 1.0").unwrap();
+
+        drop(journal);
+
+        let (mut journal, entries) = Journal::open(&config, 0).unwrap();
+
+        assert_eq!(entries.len(), 1);
+
+        journal.append(3, &deposit(3)).unwrap();
+
+        drop(journal);
+
+        let (_, entries) = Journal::open(&config, 0).unwrap();
+
+        assert_eq!(entries.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), [1, 3]);
+
+        fs::remove_dir_all(&config.directory).unwrap();
+    }
+}
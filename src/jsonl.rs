@@ -0,0 +1,269 @@
+//! JSON Lines decoder
+//!
+//! Every line is a JSON object with the keys `type`, `client`, `tx` and `amount`:
+//!
+//! ```text
+//! {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+//! {"type": "dispute", "client": 1, "tx": 1}
+//! ```
+//!
+//! Ids and amounts can be given as numbers or strings, `null` stands for an empty field and unknown keys are ignored.
+//! The fields are validated exactly like the fields of a CSV row.
+
+use std::borrow::Cow;
+
+use nom::{
+    IResult,
+    branch::alt,
+    bytes::complete::{is_not, tag, take_while_m_n},
+    character::complete::{char, digit0, digit1, multispace0, one_of},
+    combinator::{map, map_opt, opt, recognize, value, verify},
+    multi::fold_many0,
+    sequence::{pair, preceded, terminated, tuple},
+};
+
+use crate::{
+    LedgerItem,
+    error::{ParseError, ParseErrorKind},
+    processor::{AMOUNT, CLIENT, ExcessPrecision, Field, TX, TYPE, decode_fields},
+};
+
+/// Parses a character escaped with a backslash, the backslash is already consumed
+fn parse_escape(input: &str) -> IResult<&str, char> {
+    alt((
+        value('"', char('"')),
+        value('\\', char('\\')),
+        value('/', char('/')),
+        value('\u{8}', char('b')),
+        value('\u{c}', char('f')),
+        value('\n', char('n')),
+        value('\r', char('r')),
+        value('\t', char('t')),
+        parse_unicode_escape,
+    ))(input)
+}
+
+/// Parses the UTF-16 code unit of a `\u` escape, the backslash is already consumed
+fn parse_code_unit(input: &str) -> IResult<&str, u32> {
+    map_opt(
+        preceded(char('u'), take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit())),
+        |hex| u32::from_str_radix(hex, 16).ok(),
+    )(input)
+}
+
+/// Parses a `\u` escape, a surrogate pair of two escapes is combined into one character and lone surrogates are rejected
+fn parse_unicode_escape(input: &str) -> IResult<&str, char> {
+    alt((
+        map_opt(
+            pair(
+                verify(parse_code_unit, |unit| (0xD800..0xDC00).contains(unit)),
+                preceded(char('\\'), verify(parse_code_unit, |unit| (0xDC00..0xE000).contains(unit))),
+            ),
+            |(high, low)| std::char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)),
+        ),
+        map_opt(parse_code_unit, std::char::from_u32),
+    ))(input)
+}
+
+fn parse_string(input: &str) -> IResult<&str, Cow<'_, str>> {
+    preceded(
+        char('"'),
+        terminated(
+            fold_many0(
+                alt((
+                    map(is_not("\"\\"), Cow::Borrowed),
+                    map(preceded(char('\\'), parse_escape), |c| Cow::Owned(c.to_string())),
+                )),
+                Cow::Borrowed(""),
+                |mut string: Cow<'_, str>, part| {
+                    if string.is_empty() {
+                        part
+                    } else {
+                        string.to_mut().push_str(&part);
+                        string
+                    }
+                },
+            ),
+            char('"'),
+        ),
+    )(input)
+}
+
+/// Recognizes a number, its text is validated like a CSV field
+///
+/// The integral part has no leading zeros, e.g. `007` is malformed
+fn parse_number(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        opt(char('-')),
+        alt((tag("0"), recognize(pair(one_of("123456789"), digit0)))),
+        opt(pair(char('.'), digit1)),
+        opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+    )))(input)
+}
+
+/// Parses a value which is not an object or array, `null` is an empty field
+fn parse_value(input: &str) -> IResult<&str, Cow<'_, str>> {
+    alt((
+        parse_string,
+        map(parse_number, Cow::Borrowed),
+        map(alt((tag("true"), tag("false"))), Cow::Borrowed),
+        value(Cow::Borrowed(""), tag("null")),
+    ))(input)
+}
+
+/// Splits a line into the values of its keys
+fn parse_object(input: &str) -> Result<Vec<(Cow<'_, str>, Field<'_>)>, ParseError> {
+    let offset = |rest: &str| input.len() - rest.len();
+    let malformed = |rest: &str| ParseError::new(ParseErrorKind::MalformedField, offset(rest), None, rest);
+
+    let skip = |rest| multispace0::<&str, ()>(rest).map(|(rest, _)| rest).unwrap_or(rest);
+
+    let mut rest = skip(input).strip_prefix('{').ok_or_else(|| malformed(input))?;
+    let mut fields = Vec::new();
+
+    rest = skip(rest);
+
+    if let Some(remaining) = rest.strip_prefix('}') {
+        rest = remaining;
+    } else {
+        loop {
+            let (remaining, key) = parse_string(rest).map_err(|_| malformed(rest))?;
+            let remaining = skip(remaining).strip_prefix(':').ok_or_else(|| malformed(remaining))?;
+
+            let start = skip(remaining);
+            let (remaining, value) = parse_value(start).map_err(|_| malformed(start))?;
+
+            fields.push((key, Field { offset: offset(start), value }));
+
+            let remaining = skip(remaining);
+
+            if let Some(remaining) = remaining.strip_prefix(',') {
+                rest = skip(remaining);
+            } else if let Some(remaining) = remaining.strip_prefix('}') {
+                rest = remaining;
+                break;
+            } else {
+                return Err(malformed(remaining));
+            }
+        }
+    }
+
+    let rest = skip(rest);
+
+    if rest.is_empty() {
+        Ok(fields)
+    } else {
+        Err(malformed(rest))
+    }
+}
+
+/// Decodes a single line
+///
+/// Amounts with more fractional digits than `TxAmount` can represent are rejected.
+/// The line number of the returned error is not set, see `ParseError::at_line`
+pub fn parse_json_line(input: &str) -> Result<LedgerItem, ParseError> {
+    parse_json_line_with(ExcessPrecision::Reject, input).map(|(item, _)| item)
+}
+
+/// Decodes a single line
+///
+/// Amounts with more fractional digits than `TxAmount` can represent are handled according to excess_precision.
+/// Returns the item and whether its amount had to be rounded or truncated.
+/// The line number of the returned error is not set, see `ParseError::at_line`
+pub fn parse_json_line_with(excess_precision: ExcessPrecision, input: &str) -> Result<(LedgerItem, bool), ParseError> {
+    let fields = parse_object(input)?;
+
+    for (index, (key, field)) in fields.iter().enumerate() {
+        if let Some(&column) = [TYPE, CLIENT, TX, AMOUNT].iter().find(|column| **column == key) {
+            if fields[..index].iter().any(|(other, _)| other == key) {
+                return Err(ParseError::new(ParseErrorKind::DuplicateField, field.offset, Some(column), &field.value));
+            }
+        }
+    }
+
+    let field = |column| fields.iter().find(|(key, _)| key == column).map(|(_, field)| field);
+
+    decode_fields(field, input.len(), excess_precision)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ExcessPrecision, LedgerAction, TxAmount, error::ParseErrorKind};
+
+    use super::{parse_json_line, parse_json_line_with, parse_string};
+
+    #[test]
+    fn items() {
+        let item = parse_json_line(r#"{"type":"deposit","client":1,"tx":2,"amount":"1.5"}"#).unwrap();
+
+        assert_eq!((item.client_id, item.tx_id, item.action), (1, 2, LedgerAction::Deposit(TxAmount::new(15000))));
+
+        let item = parse_json_line(r#" { "amount": 2.25, "tx": "3", "client": "4", "type": "withdrawal", "note": "atm" } "#).unwrap();
+
+        assert_eq!((item.client_id, item.tx_id, item.action), (4, 3, LedgerAction::Withdrawal(TxAmount::new(22500))));
+
+        let item = parse_json_line(r#"{"type": "dispute", "client": 1, "tx": 2, "amount": null}"#).unwrap();
+
+        assert_eq!((item.client_id, item.tx_id, item.action), (1, 2, LedgerAction::Dispute(None)));
+
+        let item = parse_json_line(r#"{"type": "resolve", "client": 1, "tx": 2}"#).unwrap();
+
+        assert_eq!(item.action, LedgerAction::Resolve);
+
+        let item = parse_json_line(r#"{"type": "chargeback", "client": 1, "tx": 2, "amount": 0.5}"#).unwrap();
+
+        assert_eq!(item.action, LedgerAction::Chargeback(Some(TxAmount::new(5000))));
+    }
+
+    #[test]
+    fn errors() {
+        let kind = |input| parse_json_line(input).unwrap_err().kind;
+
+        assert_eq!(kind(r#"{"type": "deposit", "client": 1, "tx": 2}"#), ParseErrorKind::MissingField);
+        assert_eq!(kind(r#"{"type": "transfer", "client": 1, "tx": 2, "amount": 1}"#), ParseErrorKind::UnknownType);
+        assert_eq!(kind(r#"{"type": "deposit", "client": -1, "tx": 2, "amount": 1}"#), ParseErrorKind::InvalidClientId);
+        assert_eq!(kind(r#"{"type": "deposit", "client": 1, "tx": 4294967296, "amount": 1}"#), ParseErrorKind::Overflow);
+        assert_eq!(kind(r#"{"type": "deposit", "client": 1, "tx": 2, "amount": 1e3}"#), ParseErrorKind::InvalidAmount);
+        assert_eq!(kind(r#"{"type": "deposit", "client": 1, "tx": 2, "amount": "1.23456"}"#), ParseErrorKind::TooManyDecimals);
+        assert_eq!(kind(r#"{"type": "resolve", "client": 1, "tx": 2, "amount": "1.0"}"#), ParseErrorKind::UnexpectedAmount);
+        assert_eq!(kind(r#"{"type": "dispute", "client": 1, "tx": 2, "client": 3}"#), ParseErrorKind::DuplicateField);
+        assert_eq!(kind(r#"{"type": "deposit", "client": 1, "tx": 2, "amount": {"value": 1}}"#), ParseErrorKind::MalformedField);
+        assert_eq!(kind(r#"{"type": "deposit", "client": 1"#), ParseErrorKind::MalformedField);
+        assert_eq!(kind(r#"{"type": "deposit"} 1"#), ParseErrorKind::MalformedField);
+        assert_eq!(kind(""), ParseErrorKind::MalformedField);
+
+        // Numbers have no leading zeros
+        assert_eq!(kind(r#"{"type": "deposit", "client": 1, "tx": 007, "amount": 1}"#), ParseErrorKind::MalformedField);
+        assert_eq!(kind(r#"{"type": "deposit", "client": 1, "tx": 2, "amount": -01.5}"#), ParseErrorKind::MalformedField);
+        assert_eq!(parse_json_line(r#"{"type": "deposit", "client": 1, "tx": 0, "amount": 0.5}"#).unwrap().tx_id, 0);
+
+        let error = parse_json_line(r#"{"type": "deposit", "client": 1, "tx": 2, "amount": "x"}"#).unwrap_err();
+
+        assert_eq!((error.offset, error.column, error.field.as_str()), (52, Some("amount"), "x"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(parse_string(r#""a\"\\\/\n\u00e9""#).unwrap().1, "a\"\\/\né");
+
+        // Characters outside the basic multilingual plane are escaped as a surrogate pair
+        assert_eq!(parse_string(r#""\ud83d\ude00""#).unwrap().1, "\u{1f600}");
+        assert_eq!(parse_string(r#""\uD834\uDD1E!""#).unwrap().1, "\u{1d11e}!");
+
+        assert!(parse_string(r#""\ud83d""#).is_err());
+        assert!(parse_string(r#""\ude00\ud83d""#).is_err());
+        assert!(parse_string(r#""\ud83d\u0041""#).is_err());
+
+        let item = parse_json_line(r#"{"type": "deposit", "client": 1, "tx": 2, "amount": 1, "note": "\ud83d\ude00"}"#).unwrap();
+
+        assert_eq!(item.action, LedgerAction::Deposit(TxAmount::new(10000)));
+    }
+
+    #[test]
+    fn excess_precision() {
+        let (item, adjusted) = parse_json_line_with(ExcessPrecision::Truncate, r#"{"type": "deposit", "client": 1, "tx": 2, "amount": 1.23456}"#).unwrap();
+
+        assert_eq!(item.action, LedgerAction::Deposit(TxAmount::new(12345)));
+        assert!(adjusted);
+    }
+}
@@ -0,0 +1,94 @@
+//! Transaction processing engine
+//!
+//! Processes a ledger of deposits, withdrawals, disputes, resolves and chargebacks into client accounts.
+//!
+//! * [`processor`] decodes CSV rows into [`LedgerItem`]s, [`jsonl`] decodes JSON Lines
+//! * [`Account`] applies ledger items to the balances of a single client
+//! * [`AccountManager`] keeps the accounts of all clients
+//! * [`AccountManagerLoadbalancer`] shards the clients over several tokio tasks, each running an `AccountManager`
+//! * [`policy`] selects how disputes of withdrawals change the balances
+//! * [`journal`] persists the processed items of every shard to recover from a crash
+//! * [`snapshot`] stores the accounts of all clients to continue processing in a later run
+//!
+//! ```
+//! use transaction_processor::{Account, Columns, TxAmount, parse_line};
+//!
+//! let columns = Columns::parse_header("type, client, tx, amount").unwrap();
+//! let mut account = Account::new();
+//!
+//! account.process(parse_line(&columns, "deposit, 1, 1, 1.5").unwrap()).unwrap();
+//! account.process(parse_line(&columns, "withdrawal, 1, 2, 0.5").unwrap()).unwrap();
+//!
+//! assert_eq!(account.available(), TxAmount::new(10000));
+//! ```
+
+use std::fmt::Display;
+
+pub mod account;
+pub mod account_manager;
+pub mod error;
+pub mod processor;
+pub mod transaction;
+pub mod fp_isize;
+pub mod fp_i128;
+pub mod journal;
+pub mod jsonl;
+pub mod policy;
+pub mod reject;
+pub mod snapshot;
+
+pub use account::{Account, AccountSnapshot, LockReason, TransactionCounts};
+pub use account_manager::{AccountManager, AccountManagerLoadbalancer, AccountManagerTask, ManagerConfig};
+pub use error::{ParseError, ParseErrorKind, ProcessorError};
+pub use fp_isize::{FpIsize, FpParseError, RoundingMode};
+pub use fp_i128::FpI128;
+pub use jsonl::{parse_json_line, parse_json_line_with};
+pub use policy::{DisputePolicy, Policy};
+pub use snapshot::Snapshot;
+pub use processor::{Columns, ExcessPrecision, format_line, parse_line, parse_line_with};
+
+/// Identifier of a client account
+pub type ClientID   = u16;
+/// Globally unique identifier of a deposit or withdrawal
+pub type TxID       = u32;
+/// Amount of a transaction, a fixed-point number with 4 fractional digits
+#[cfg(not(feature = "i128-amounts"))]
+pub type TxAmount   = FpIsize<4>;
+/// Amount of a transaction, a fixed-point number with 4 fractional digits
+#[cfg(feature = "i128-amounts")]
+pub type TxAmount   = FpI128<4>;
+
+/// Operation of a ledger item
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum LedgerAction {
+    Deposit(TxAmount),
+    Withdrawal(TxAmount),
+    /// Holds the given part of the transaction, the whole remaining amount without one
+    Dispute(Option<TxAmount>),
+    Resolve,
+    /// Charges back the given part of the disputed amount, the whole disputed amount without one
+    Chargeback(Option<TxAmount>),
+}
+
+impl Display for LedgerAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LedgerAction::Deposit(amount) => write!(f, "deposit ({})", amount),
+            LedgerAction::Withdrawal(amount) => write!(f, "withdrawal ({})", amount),
+            LedgerAction::Dispute(Some(amount)) => write!(f, "dispute ({})", amount),
+            LedgerAction::Dispute(None) => write!(f, "dispute"),
+            LedgerAction::Resolve => write!(f, "resolve"),
+            LedgerAction::Chargeback(Some(amount)) => write!(f, "chargeback ({})", amount),
+            LedgerAction::Chargeback(None) => write!(f, "chargeback"),
+        }
+    }
+}
+
+/// A single row of the ledger
+#[derive(Debug, Clone, Copy)]
+pub struct LedgerItem {
+    pub client_id:  ClientID,
+    pub tx_id:      TxID,
+
+    pub action:     LedgerAction,
+}
@@ -1,118 +1,215 @@
-use std::{env::args, fmt::Display};
-
-use tokio::{fs::File, io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter}, sync::mpsc::channel};
-
-mod account;
-mod account_manager;
-mod error;
-mod processor;
-mod transaction;
-mod fp_isize;
-
-use processor::parse_line;
-use fp_isize::FpIsize;
-use account_manager::AccountManagerLoadbalancer;
-
-pub type ClientID   = u16;
-pub type TxID       = u32;
-pub type TxAmount   = FpIsize<4>;
-
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
-pub enum LedgerAction {
-    Deposit(TxAmount),
-    Withdrawal(TxAmount),
-    Dispute,
-    Resolve,
-    Chargeback,
+use std::{env::args, path::{Path, PathBuf}, sync::Arc};
+
+use tokio::{io::{self, AsyncBufReadExt, BufReader}, sync::mpsc::channel};
+
+use transaction_processor::{
+    AccountManagerLoadbalancer, Columns, ManagerConfig, Snapshot, parse_json_line_with, parse_line_with,
+    journal::{Journal, JournalConfig},
+    reject::{Origin, RejectLog, Rejection},
+};
+
+mod input;
+mod options;
+mod output;
+
+use input::Format;
+use options::{Options, USAGE};
+use output::{AccountWriter, Output};
+
+/// Counters over all input files
+#[derive(Default)]
+struct Progress {
+    /// Number of amounts rounded or truncated according to the excess precision policy
+    adjusted_amounts:   usize,
+    /// Number of items submitted to the account manager
+    items:              usize,
 }
 
-impl Display for LedgerAction {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            LedgerAction::Deposit(amount) => write!(f, "deposit ({})", amount),
-            LedgerAction::Withdrawal(amount) => write!(f, "withdrawal ({})", amount),
-            LedgerAction::Dispute => write!(f, "dispute"),
-            LedgerAction::Resolve => write!(f, "resolve"),
-            LedgerAction::Chargeback => write!(f, "chargeback"),
-        }
-    }
-}
+/// Parses a file and submits its items to the account manager
+///
+/// Every CSV file starts with its own header, the line numbers in errors and rejections are relative to the file
+async fn process_file(
+    path:               &Path,
+    options:            &Options,
+    account_manager:    &mut AccountManagerLoadbalancer,
+    rejects:            Option<&RejectLog>,
+    progress:           &mut Progress,
+) -> io::Result<()> {
+    let file: Arc<str> = path.to_string_lossy().into();
 
-#[derive(Debug)]
-pub struct LedgerItem {
-    client_id:  ClientID,
-    tx_id:      TxID,
+    let reader = BufReader::new(input::open(path).await?);
 
-    action:     LedgerAction,
-}
-
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    let filename = if let Some(filename) = args().nth(1) {
-        filename
-    } else {
-        eprintln!("transaction-processor <filename>");
+    let mut lines = reader.lines();
 
-        return Ok(());
+    // Physical line number, the header of a CSV file is line 1
+    let mut line_number = 0;
+
+    // Column map of a CSV file, JSON Lines have no header
+    let columns = match options.input_format.unwrap_or_else(|| Format::from_extension(path)) {
+        Format::Csv => {
+            line_number += 1;
+
+            if let Some(header) = lines.next_line().await? {
+                Some(Columns::parse_header(header.as_str()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file, e)))?)
+            } else {
+                Some(Columns::default())
+            }
+        },
+        Format::JsonLines => None,
     };
 
-    let input = File::open(filename).await?;
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
 
-    let reader = BufReader::new(input);
-    let account_manager = AccountManagerLoadbalancer::spawn(8);
+        let parsed = match &columns {
+            Some(columns) => parse_line_with(columns, options.excess_precision, line.as_str()),
+            None => parse_json_line_with(options.excess_precision, line.as_str()),
+        };
 
-    let mut index = 0;
+        match parsed {
+            Ok((item, adjusted)) => {
+                if adjusted {
+                    progress.adjusted_amounts += 1;
+                }
 
-    let mut lines = reader.lines();
+                let origin = rejects.map(|_| Origin { file: file.clone(), line: line_number, row: line });
 
-    // Skip header
-    lines.next_line().await.unwrap();
+                account_manager.process(item, origin).await?;
 
-    while let Some(line) = lines.next_line().await? {
-        match parse_line(line.as_str()) {
-            Ok(item) => {
-                account_manager.process(item).await
+                progress.items += 1;
+
+                if let (Some(path), Some(every)) = (&options.snapshot, options.snapshot_every) {
+                    if progress.items % every == 0 {
+                        account_manager.snapshot().await?.write(Path::new(path))?;
+                    }
+                }
             },
-            Err(_) => {
-                eprintln!("[Line {}] Could not parse line", index);
+            Err(e) => {
+                let e = e.at_line(line_number);
+
+                eprintln!("{}: {}", file, e);
+
+                // Rows before the last recovered item were already rejected before the crash, rows after it
+                // are rejected again as it is unknown whether they were written
+                let reported = (progress.items as u64) < account_manager.recovered();
+
+                if let Some(rejects) = rejects.filter(|_| !reported) {
+                    rejects.reject(Rejection::parse(Origin { file: file.clone(), line: line_number, row: line }, &e)).await?;
+                }
             },
         }
+    }
+
+    Ok(())
+}
+
+/// Restores the accounts, processes the inputs and writes the state and the balances
+///
+/// The journal is only cleared by the caller once the run and the rejects log completed
+async fn run(options: &Options, inputs: &[PathBuf], journal: Option<&JournalConfig>, rejects: Option<&RejectLog>) -> io::Result<()> {
+    // With --state the run continues from the state file of the previous run, if there is one
+    let restore = match &options.state {
+        Some(path) if Path::new(path).exists() => Some(path),
+        Some(_) => None,
+        None => options.restore.as_ref(),
+    };
+
+    let snapshot = if let Some(path) = restore {
+        Snapshot::read(Path::new(path))?
+    } else {
+        Snapshot::new(options.policy)
+    };
 
-        index += 1;
+    let mut account_manager = AccountManagerLoadbalancer::restore(8, ManagerConfig {
+        rejects: rejects.map(RejectLog::sender),
+        journal: journal.cloned(),
+        policy: options.policy,
+    }, snapshot)?;
+
+    if account_manager.recovered() > 0 {
+        eprintln!("Recovered the state up to item {} from the journal", account_manager.recovered());
+    }
+
+    let mut progress = Progress::default();
+
+    for path in inputs.iter() {
+        process_file(path, options, &mut account_manager, rejects, &mut progress).await?;
+    }
+
+    if progress.adjusted_amounts > 0 {
+        eprintln!("Adjusted {} amounts with excess precision ({:?})", progress.adjusted_amounts, options.excess_precision);
+    }
+
+    if let Some(path) = options.snapshot.as_ref().or(options.state.as_ref()) {
+        account_manager.snapshot().await?.write(Path::new(path))?;
     }
 
     let (sender, mut receiver) = channel(128);
     
-    account_manager.dump(sender).await;
+    if options.sorted {
+        account_manager.dump_sorted(sender).await?;
+    } else {
+        account_manager.dump(sender).await?;
+    }
 
-    let stdout = tokio::io::stdout();
-    let mut writer = BufWriter::new(stdout);
+    let output_path = options.output.as_ref().map(Path::new);
+    let output_format = options.output_format.or_else(|| output_path.map(output::Format::from_extension)).unwrap_or(output::Format::Csv);
 
-    let header = b"client, available, held, total, locked\n";
-    
-    writer.write_all(header).await?;
-    
-    while let Some((client_id, available, held, total, locked)) = receiver.recv().await {
-        writer.write_all(client_id.to_string().as_bytes()).await?;
-        writer.write_all(b", ").await?;
+    let mut writer = AccountWriter::create(Output::create(output_path).await?, output_format).await?;
 
-        writer.write_all(available.to_string().as_bytes()).await?;
-        writer.write_all(b", ").await?;
+    while let Some(account) = receiver.recv().await {
+        writer.write(&account).await?;
+    }
 
-        writer.write_all(held.to_string().as_bytes()).await?;
-        writer.write_all(b", ").await?;
+    // Fails if writing the journal failed, the journal is kept for the next run
+    account_manager.stop().await?;
+    account_manager.join().await?;
 
-        writer.write_all(total.to_string().as_bytes()).await?;
-        writer.write_all(b", ").await?;
+    writer.finish().await
+}
 
-        writer.write_all(locked.to_string().as_bytes()).await?;
-        writer.write_all(b"\n").await?;
-    }
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let options = match Options::parse(args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
 
-    account_manager.stop().await;
-    account_manager.join().await;
+            std::process::exit(2);
+        }
+    };
+
+    let inputs = input::expand(&options.inputs)?;
+
+    let journal = options.journal.as_ref().map(|directory| JournalConfig {
+        directory:  PathBuf::from(directory),
+        fsync:      options.fsync,
+    });
 
-    writer.flush().await.unwrap();
+    // A run recovering from the journal keeps the rows rejected before the crash
+    let rejects = if let Some(path) = &options.rejects {
+        let recovering = journal.as_ref().is_some_and(|journal| Journal::exists(&journal.directory));
+
+        Some(RejectLog::create(path, recovering).await?)
+    } else {
+        None
+    };
+
+    let result = run(&options, &inputs, journal.as_ref(), rejects.as_ref()).await;
+
+    // The rows rejected so far are written even if the run failed, the next run appends to them when recovering
+    let joined = match rejects {
+        Some(rejects) => rejects.join().await,
+        None => Ok(()),
+    };
+
+    result.and(joined)?;
+
+    // The run is complete, the next one starts from scratch
+    if let Some(journal) = journal {
+        Journal::clear(&journal.directory)?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,211 @@
+use transaction_processor::{ExcessPrecision, Policy, journal::FsyncPolicy};
+
+use crate::{input, output};
+
+/// Command line options of the transaction processor
+#[derive(PartialEq, Eq, Debug, Default)]
+pub struct Options {
+    /// Files, directories or file name patterns, processed in order
+    pub inputs:             Vec<String>,
+    /// Format of all inputs, detected from the file extension otherwise
+    pub input_format:       Option<input::Format>,
+    pub rejects:            Option<String>,
+    pub excess_precision:   ExcessPrecision,
+    pub journal:            Option<String>,
+    pub fsync:              FsyncPolicy,
+    pub snapshot:           Option<String>,
+    pub snapshot_every:     Option<usize>,
+    pub restore:            Option<String>,
+    /// Restored if it exists and replaced with the snapshot at the end of the run
+    pub state:              Option<String>,
+    /// Written atomically instead of stdout
+    pub output:             Option<String>,
+    /// Format of the balances, detected from the extension of the output file otherwise
+    pub output_format:      Option<output::Format>,
+    /// Sorts the accounts by client id instead of grouping them by task
+    pub sorted:             bool,
+    /// Dispute policy and maximum number of dispute cycles
+    pub policy:             Policy,
+}
+
+pub const USAGE: &str = "transaction-processor [--rejects <file>] [--excess-precision reject|truncate|round|round:<mode>] [--journal <directory>] [--fsync always|never|every:<entries>] [--restore <file>] [--snapshot <file> [--snapshot-every <items>]] [--state <file>] [--output <file>] [--output-format csv|json|jsonl|ndjson] [--sorted] [--dispute-policy symmetric|deny-withdrawals|pending-credit] [--max-dispute-cycles <cycles>] [--input-format csv|jsonl] <input>...";
+
+impl Options {
+
+    /// Parses the command line arguments, without the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("Missing value for {}", name));
+
+            match arg.as_str() {
+                "--rejects" => options.rejects = Some(value("--rejects")?),
+                "--excess-precision" => options.excess_precision = value("--excess-precision")?.parse()?,
+                "--journal" => options.journal = Some(value("--journal")?),
+                "--fsync" => options.fsync = value("--fsync")?.parse()?,
+                "--snapshot" => options.snapshot = Some(value("--snapshot")?),
+                "--snapshot-every" => {
+                    match value("--snapshot-every")?.parse() {
+                        Ok(0) | Err(_) => return Err("The number of items between snapshots must be a positive number".to_string()),
+                        Ok(items) => options.snapshot_every = Some(items),
+                    }
+                },
+                "--restore" => options.restore = Some(value("--restore")?),
+                "--state" => options.state = Some(value("--state")?),
+                "--output" => options.output = Some(value("--output")?),
+                "--sorted" => options.sorted = true,
+                "--output-format" => options.output_format = Some(value("--output-format")?.parse()?),
+                "--dispute-policy" => options.policy.dispute = value("--dispute-policy")?.parse()?,
+                "--max-dispute-cycles" => {
+                    match value("--max-dispute-cycles")?.parse() {
+                        Ok(0) | Err(_) => return Err("The maximum number of dispute cycles must be a positive number".to_string()),
+                        Ok(cycles) => options.policy.max_dispute_cycles = cycles,
+                    }
+                },
+                "--input-format" => options.input_format = Some(value("--input-format")?.parse()?),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => options.inputs.push(arg),
+            }
+        }
+
+        if options.inputs.is_empty() {
+            return Err("Missing input file".to_string());
+        }
+
+        if options.inputs.iter().filter(|input| *input == "-").count() > 1 {
+            return Err("stdin can only be read once".to_string());
+        }
+
+        if options.state.is_some() && (options.restore.is_some() || options.snapshot.is_some()) {
+            return Err("--state cannot be combined with --restore or --snapshot".to_string());
+        }
+
+        if options.snapshot_every.is_some() {
+            if options.snapshot.is_none() {
+                return Err("--snapshot-every requires --snapshot".to_string());
+            }
+
+            // A snapshot in the middle of the run must not replace the state the run started from
+            if options.snapshot == options.restore {
+                return Err("--snapshot-every cannot overwrite the restored snapshot".to_string());
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use transaction_processor::{DisputePolicy, ExcessPrecision, Policy, RoundingMode, journal::FsyncPolicy};
+
+    use crate::{input::Format, output};
+
+    use super::Options;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn input() {
+        assert_eq!(parse(&["transactions.csv"]), Ok(Options { inputs: vec!["transactions.csv".to_string()], ..Options::default() }));
+        assert!(parse(&[]).is_err());
+        assert_eq!(parse(&["a.csv", "daily/*.csv"]).unwrap().inputs, ["a.csv", "daily/*.csv"]);
+    }
+
+    #[test]
+    fn rejects() {
+        let options = parse(&["--rejects", "rejects.csv", "transactions.csv"]).unwrap();
+
+        assert_eq!(options.rejects, Some("rejects.csv".to_string()));
+        assert_eq!(options.inputs, ["transactions.csv"]);
+
+        assert!(parse(&["transactions.csv", "--rejects"]).is_err());
+        assert!(parse(&["--unknown", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn excess_precision() {
+        assert_eq!(parse(&["transactions.csv"]).unwrap().excess_precision, ExcessPrecision::Reject);
+
+        let options = parse(&["--excess-precision", "round:floor", "transactions.csv"]).unwrap();
+
+        assert_eq!(options.excess_precision, ExcessPrecision::Round(RoundingMode::Floor));
+
+        assert!(parse(&["--excess-precision", "round:nearest", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn journal() {
+        let options = parse(&["--journal", "journal", "--fsync", "every:100", "transactions.csv"]).unwrap();
+
+        assert_eq!(options.journal, Some("journal".to_string()));
+        assert_eq!(options.fsync, FsyncPolicy::Every(100));
+
+        assert_eq!(parse(&["transactions.csv"]).unwrap().fsync, FsyncPolicy::default());
+        assert!(parse(&["--fsync", "sometimes", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn snapshot() {
+        let options = parse(&["--restore", "monday.snapshot", "--snapshot", "tuesday.snapshot", "--snapshot-every", "1000", "transactions.csv"]).unwrap();
+
+        assert_eq!(options.restore, Some("monday.snapshot".to_string()));
+        assert_eq!(options.snapshot, Some("tuesday.snapshot".to_string()));
+        assert_eq!(options.snapshot_every, Some(1000));
+
+        assert!(parse(&["--restore", "state.snapshot", "--snapshot", "state.snapshot", "transactions.csv"]).is_ok());
+        assert!(parse(&["--restore", "state.snapshot", "--snapshot", "state.snapshot", "--snapshot-every", "10", "transactions.csv"]).is_err());
+        assert!(parse(&["--snapshot-every", "10", "transactions.csv"]).is_err());
+        assert!(parse(&["--snapshot", "state.snapshot", "--snapshot-every", "0", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn state() {
+        assert_eq!(parse(&["--state", "state.snapshot", "transactions.csv"]).unwrap().state, Some("state.snapshot".to_string()));
+
+        assert!(parse(&["--state", "state.snapshot", "--restore", "monday.snapshot", "transactions.csv"]).is_err());
+        assert!(parse(&["--state", "state.snapshot", "--snapshot", "tuesday.snapshot", "transactions.csv"]).is_err());
+    }
+
+    #[test]
+    fn stdin_and_output() {
+        let options = parse(&["--output", "accounts.csv", "-"]).unwrap();
+
+        assert_eq!(options.inputs, ["-"]);
+        assert_eq!(options.output, Some("accounts.csv".to_string()));
+
+        assert!(parse(&["-", "transactions.csv", "-"]).is_err());
+    }
+
+    #[test]
+    fn input_format() {
+        assert_eq!(parse(&["-"]).unwrap().input_format, None);
+        assert_eq!(parse(&["--input-format", "jsonl", "-"]).unwrap().input_format, Some(Format::JsonLines));
+        assert!(parse(&["--input-format", "xml", "-"]).is_err());
+    }
+
+    #[test]
+    fn output_format() {
+        assert_eq!(parse(&["-"]).unwrap().output_format, None);
+        assert_eq!(parse(&["--output-format", "json", "-"]).unwrap().output_format, Some(output::Format::Json));
+        assert!(parse(&["--output-format", "xml", "-"]).is_err());
+
+        assert!(!parse(&["-"]).unwrap().sorted);
+        assert!(parse(&["--sorted", "-"]).unwrap().sorted);
+    }
+
+    #[test]
+    fn policy() {
+        assert_eq!(parse(&["-"]).unwrap().policy, Policy::default());
+        assert_eq!(parse(&["--dispute-policy", "pending-credit", "-"]).unwrap().policy.dispute, DisputePolicy::PendingCredit);
+        assert!(parse(&["--dispute-policy", "never", "-"]).is_err());
+
+        assert_eq!(parse(&["--max-dispute-cycles", "2", "-"]).unwrap().policy.max_dispute_cycles, 2);
+        assert!(parse(&["--max-dispute-cycles", "0", "-"]).is_err());
+    }
+}
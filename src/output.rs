@@ -0,0 +1,288 @@
+use std::{path::{Path, PathBuf}, str::FromStr};
+
+use tokio::{fs::File, io::{self, AsyncWrite, AsyncWriteExt, BufWriter}};
+
+use transaction_processor::{AccountSnapshot, LockReason, snapshot::{replace_file, temporary_path}};
+
+/// Format of the account balances
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Format {
+    /// CSV with the header "client, available, held, total, locked"
+    Csv,
+    /// A JSON array of account objects
+    Json,
+    /// One account object per line
+    JsonLines,
+}
+
+impl Format {
+    /// Format according to the file extension, `.json` and `.jsonl` select JSON and JSON Lines, all others CSV
+    pub fn from_extension(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Format::Json,
+            Some("jsonl") | Some("ndjson") => Format::JsonLines,
+            _ => Format::Csv,
+        }
+    }
+
+    fn header(&self) -> &'static str {
+        match self {
+            Format::Csv => "client, available, held, total, locked\n",
+            Format::Json => "[",
+            Format::JsonLines => "",
+        }
+    }
+
+    /// Formats an account, index is the position of the account in the output
+    ///
+    /// CSV only contains the balances and lock status. JSON also contains the lock reason, transaction counts and
+    /// last activity, amounts are written as exact decimal strings as a number would be read as float by most consumers
+    fn record(&self, index: usize, account: &AccountSnapshot) -> String {
+        match self {
+            Format::Csv => format!("{}, {}, {}, {}, {}\n",
+                account.client_id, account.available, account.held, account.total, account.locked,
+            ),
+            Format::Json | Format::JsonLines => {
+                let lock_reason = match account.lock_reason {
+                    Some(LockReason::Chargeback(tx_id)) => format!(r#"{{"type":"chargeback","tx":{}}}"#, tx_id),
+                    None => "null".to_string(),
+                };
+
+                let last_activity = account.last_activity.map(|tx_id| tx_id.to_string()).unwrap_or_else(|| "null".to_string());
+
+                let counts = account.transactions;
+
+                let object = format!(
+                    concat!(
+                        r#"{{"client":{},"available":"{}","held":"{}","total":"{}","locked":{},"lock_reason":{},"#,
+                        r#""transactions":{{"deposits":{},"withdrawals":{},"disputes":{},"resolves":{},"chargebacks":{}}},"#,
+                        r#""last_activity":{}}}"#,
+                    ),
+                    account.client_id, account.available, account.held, account.total, account.locked, lock_reason,
+                    counts.deposits, counts.withdrawals, counts.disputes, counts.resolves, counts.chargebacks,
+                    last_activity,
+                );
+
+                match (self, index) {
+                    (Format::JsonLines, _) => object + "\n",
+                    (_, 0) => format!("\n  {}", object),
+                    _ => format!(",\n  {}", object),
+                }
+            },
+        }
+    }
+
+    fn footer(&self, count: usize) -> &'static str {
+        match (self, count) {
+            (Format::Json, 0) => "]\n",
+            (Format::Json, _) => "\n]\n",
+            _ => "",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "jsonl" | "ndjson" => Ok(Format::JsonLines),
+            _ => Err("Unknown output format, expected csv, json, jsonl or ndjson"),
+        }
+    }
+}
+
+/// Writes the accounts in a format to an `Output`
+pub struct AccountWriter {
+    output: Output,
+    format: Format,
+
+    count:  usize,
+}
+
+impl AccountWriter {
+    pub async fn create(mut output: Output, format: Format) -> io::Result<Self> {
+        output.writer.write_all(format.header().as_bytes()).await?;
+
+        Ok(AccountWriter {
+            output,
+            format,
+
+            count:  0,
+        })
+    }
+
+    pub async fn write(&mut self, account: &AccountSnapshot) -> io::Result<()> {
+        let record = self.format.record(self.count, account);
+
+        self.count += 1;
+
+        self.output.writer.write_all(record.as_bytes()).await
+    }
+
+    /// Completes the format and finishes the output
+    pub async fn finish(mut self) -> io::Result<()> {
+        self.output.writer.write_all(self.format.footer(self.count).as_bytes()).await?;
+
+        self.output.finish().await
+    }
+}
+
+/// Destination of the account balances, stdout or a file
+///
+/// A file is written to a temporary file next to it which replaces the file in `finish`,
+/// so readers of the file never see a partial result. The temporary file is removed if the output is not finished
+pub struct Output {
+    writer:     BufWriter<Box<dyn AsyncWrite + Unpin + Send>>,
+    /// Temporary and final path of a file
+    file:       Option<(PathBuf, PathBuf)>,
+}
+
+impl Output {
+    pub async fn create(path: Option<&Path>) -> io::Result<Self> {
+        match path {
+            Some(path) => {
+                let temporary = temporary_path(path);
+
+                let file = File::create(&temporary).await?;
+
+                Ok(Output {
+                    writer: BufWriter::new(Box::new(file)),
+                    file:   Some((temporary, path.to_path_buf())),
+                })
+            },
+            None => Ok(Output {
+                writer: BufWriter::new(Box::new(tokio::io::stdout())),
+                file:   None,
+            }),
+        }
+    }
+
+    /// Flushes the output and moves a file into place
+    pub async fn finish(mut self) -> io::Result<()> {
+        self.writer.flush().await?;
+
+        if self.file.is_some() {
+            self.writer.shutdown().await?;
+        }
+
+        match self.file.take() {
+            Some((temporary, path)) => replace_file(&temporary, &path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some((temporary, _)) = self.file.take() {
+            let _ = std::fs::remove_file(temporary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use transaction_processor::{AccountSnapshot, LockReason, TransactionCounts, TxAmount};
+
+    use super::{AccountWriter, Format, Output};
+
+    fn render(format: Format, accounts: &[AccountSnapshot]) -> String {
+        let mut output = format.header().to_string();
+
+        for (index, account) in accounts.iter().enumerate() {
+            output += &format.record(index, account);
+        }
+
+        output + format.footer(accounts.len())
+    }
+
+    #[test]
+    fn formats() {
+        let active = AccountSnapshot {
+            client_id:      1,
+            available:      TxAmount::new(15000),
+            held:           TxAmount::zero(),
+            total:          TxAmount::new(15000),
+            locked:         false,
+            lock_reason:    None,
+            transactions:   TransactionCounts { deposits: 1, ..TransactionCounts::default() },
+            last_activity:  Some(1),
+        };
+
+        let locked = AccountSnapshot {
+            client_id:      2,
+            available:      TxAmount::zero(),
+            held:           TxAmount::new(5000),
+            total:          TxAmount::new(5000),
+            locked:         true,
+            lock_reason:    Some(LockReason::Chargeback(3)),
+            transactions:   TransactionCounts { deposits: 2, disputes: 2, chargebacks: 1, ..TransactionCounts::default() },
+            last_activity:  Some(3),
+        };
+
+        let accounts = [active, locked];
+
+        assert_eq!(render(Format::Csv, &accounts), "\
+client, available, held, total, locked
+1, 1.5000, 0.0000, 1.5000, false
+2, 0.0000, 0.5000, 0.5000, true
+");
+
+        let first = concat!(
+            r#"{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false,"lock_reason":null,"#,
+            r#""transactions":{"deposits":1,"withdrawals":0,"disputes":0,"resolves":0,"chargebacks":0},"last_activity":1}"#,
+        );
+        let second = concat!(
+            r#"{"client":2,"available":"0.0000","held":"0.5000","total":"0.5000","locked":true,"lock_reason":{"type":"chargeback","tx":3},"#,
+            r#""transactions":{"deposits":2,"withdrawals":0,"disputes":2,"resolves":0,"chargebacks":1},"last_activity":3}"#,
+        );
+
+        assert_eq!(render(Format::JsonLines, &accounts), format!("{}\n{}\n", first, second));
+        assert_eq!(render(Format::Json, &accounts), format!("[\n  {},\n  {}\n]\n", first, second));
+
+        assert_eq!(render(Format::Json, &[]), "[]\n");
+        assert_eq!(render(Format::JsonLines, &[]), "");
+    }
+
+    #[test]
+    fn format_selection() {
+        assert_eq!(Format::from_extension(Path::new("accounts.json")), Format::Json);
+        assert_eq!(Format::from_extension(Path::new("accounts.jsonl")), Format::JsonLines);
+        assert_eq!(Format::from_extension(Path::new("accounts.csv")), Format::Csv);
+        assert_eq!(Format::from_extension(Path::new("accounts.ndjson")), Format::JsonLines);
+
+        assert_eq!("json".parse(), Ok(Format::Json));
+        assert_eq!("ndjson".parse(), Ok(Format::JsonLines));
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[tokio::test]
+    async fn temporary_file() {
+        let directory = std::env::temp_dir().join(format!("transaction-processor-output-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let path = directory.join("accounts.csv");
+        let temporary = directory.join("accounts.csv.tmp");
+
+        // An unfinished output leaves neither the file nor the temporary file behind
+        let writer = AccountWriter::create(Output::create(Some(&path)).await.unwrap(), Format::Csv).await.unwrap();
+        assert!(temporary.exists());
+        drop(writer);
+
+        assert!(!path.exists());
+        assert!(!temporary.exists());
+
+        let writer = AccountWriter::create(Output::create(Some(&path)).await.unwrap(), Format::Csv).await.unwrap();
+        writer.finish().await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), Format::Csv.header());
+        assert!(!temporary.exists());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
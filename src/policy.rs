@@ -0,0 +1,85 @@
+//! Rules applied by the accounts which are selected per run
+//!
+//! Snapshots and journals record the policy they were written with, a run continuing from them
+//! is refused if it uses a different dispute policy. The maximum number of dispute cycles can change between runs.
+
+use std::{fmt::Display, str::FromStr};
+
+/// How disputes, resolves and chargebacks of withdrawals change the balances
+///
+/// Disputes of deposits hold the deposited amount under every policy. A chargeback always releases the held amount
+/// and reverses the transaction, so a charged back withdrawal credits the withdrawn amount.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum DisputePolicy {
+    /// Withdrawals are disputed like deposits with a negative amount: the withdrawn amount is credited to the
+    /// available funds and the held funds become negative until the dispute is resolved or charged back
+    #[default]
+    Symmetric,
+    /// Disputes of withdrawals are refused
+    DenyWithdrawals,
+    /// The withdrawn amount is held as a pending credit, the available funds are unchanged until it is charged back
+    PendingCredit,
+}
+
+impl Display for DisputePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisputePolicy::Symmetric => write!(f, "symmetric"),
+            DisputePolicy::DenyWithdrawals => write!(f, "deny-withdrawals"),
+            DisputePolicy::PendingCredit => write!(f, "pending-credit"),
+        }
+    }
+}
+
+impl FromStr for DisputePolicy {
+    type Err = &'static str;
+
+    /// Parses "symmetric", "deny-withdrawals" or "pending-credit", the names written by `Display`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "symmetric" => Ok(DisputePolicy::Symmetric),
+            "deny-withdrawals" => Ok(DisputePolicy::DenyWithdrawals),
+            "pending-credit" => Ok(DisputePolicy::PendingCredit),
+            _ => Err("Unknown dispute policy, expected symmetric, deny-withdrawals or pending-credit"),
+        }
+    }
+}
+
+/// Rules applied by the accounts, see `Account::process_with`
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Policy {
+    pub dispute: DisputePolicy,
+    /// Number of times a transaction can be disputed, at least 1
+    ///
+    /// A resolved transaction can be disputed again until it went through this number of dispute cycles,
+    /// e.g. 2 allows a pre-arbitration dispute after the first dispute was resolved
+    pub max_dispute_cycles: usize,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            dispute: DisputePolicy::default(),
+            max_dispute_cycles: 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DisputePolicy;
+
+    #[test]
+    fn dispute_policy() {
+        assert_eq!(DisputePolicy::default(), DisputePolicy::Symmetric);
+
+        assert_eq!("symmetric".parse(), Ok(DisputePolicy::Symmetric));
+        assert_eq!("deny-withdrawals".parse(), Ok(DisputePolicy::DenyWithdrawals));
+        assert_eq!("pending-credit".parse(), Ok(DisputePolicy::PendingCredit));
+        assert!("deny".parse::<DisputePolicy>().is_err());
+
+        for policy in [DisputePolicy::Symmetric, DisputePolicy::DenyWithdrawals, DisputePolicy::PendingCredit].iter() {
+            assert_eq!(policy.to_string().parse(), Ok(*policy));
+        }
+    }
+}
@@ -1,188 +1,393 @@
-use std::convert::TryFrom;
-
-use nom::{IResult, branch::alt, bytes::complete::tag, combinator::{map_res}};
+use std::{borrow::Cow, convert::TryFrom, str::FromStr};
+
+use nom::{
+    IResult,
+    branch::alt,
+    bytes::complete::{is_not, tag, tag_no_case, take_till},
+    character::complete::{char, digit0, digit1, space0},
+    combinator::{all_consuming, cut, map, opt},
+    multi::fold_many0,
+    sequence::{delimited, pair, preceded, terminated},
+};
 
 use crate::*;
+use crate::error::{ParseError, ParseErrorKind};
+use crate::fp_isize::{FpParseError, RoundingMode};
+
+pub(crate) const TYPE: &str = "type";
+pub(crate) const CLIENT: &str = "client";
+pub(crate) const TX: &str = "tx";
+pub(crate) const AMOUNT: &str = "amount";
+
+/// Column positions of the fields required to decode a row
+///
+/// Built from the header line of the input, so the columns can appear in any order
+/// and columns which are not known to the processor are ignored
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Columns {
+    action:     usize,
+    client_id:  usize,
+    tx_id:      usize,
+    tx_amount:  usize,
+
+    count:      usize,
+}
 
-fn parse_client_id(input: &str) -> IResult<&str, ClientID> {
-    match nom::sequence::tuple((
-        nom::character::complete::char(','),
-        nom::character::complete::space1,
-        map_res(nom::character::complete::digit1, |client_id| u16::from_str_radix(client_id, 10))
-    ))(input)
-    {
-        Ok((input, (
-            _,
-            _,
-            client_id)
-        )) => {
-            Ok((input, client_id))
-        },
-        Err(e) => Err(e),
-    }
-}
-
-fn parse_tx_id(input: &str) -> IResult<&str, TxID> {
-    match nom::sequence::tuple((
-        nom::character::complete::char(','),
-        nom::character::complete::space1,
-        map_res(nom::character::complete::digit1, |tx_id| u32::from_str_radix(tx_id, 10)),
-    ))(input)
-    {
-        Ok((input, (
-            _,
-            _,
-            tx_id,
-        ))) => {
-            Ok((input, tx_id))
-        },
-        Err(e) => Err(e),
-    }
-}
-
-fn parse_tx_amount(input: &str) -> IResult<&str, TxAmount> {
-    match nom::sequence::tuple((
-        nom::character::complete::char(','),
-        nom::character::complete::space1,
-        nom::character::complete::digit1,
-        nom::character::complete::char('.'),
-        nom::character::complete::digit1
-    ))(input)
-    {
-        Ok((input, (
-            _,
-            _,
-            tx_amount1,
-            _,
-            tx_amount2,
-        ))) => {
-            if let Ok(tx_amount) = TxAmount::try_from((tx_amount1, tx_amount2)) {
-                Ok((input, tx_amount))
-            } else {
-                Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Count)))
+impl Columns {
+
+    /// Parses the header line into a column map
+    ///
+    /// Column names are matched case-insensitively and may be quoted.
+    /// Fails when one of the required columns (type, client, tx, amount) is missing
+    /// or when a required column appears more than once
+    pub fn parse_header(input: &str) -> Result<Self, ParseError> {
+        let bom = if input.starts_with('\u{feff}') { '\u{feff}'.len_utf8() } else { 0 };
+        let names = parse_record(&input[bom..]).map_err(|e| ParseError { offset: e.offset + bom, ..e })?;
+
+        let mut action = None;
+        let mut client_id = None;
+        let mut tx_id = None;
+        let mut tx_amount = None;
+
+        for (index, name) in names.iter().enumerate() {
+            let (column, name) = match name.value.trim().to_ascii_lowercase().as_str() {
+                TYPE => (&mut action, TYPE),
+                CLIENT => (&mut client_id, CLIENT),
+                TX => (&mut tx_id, TX),
+                AMOUNT => (&mut tx_amount, AMOUNT),
+                _ => continue,
+            };
+
+            if column.replace(index).is_some() {
+                return Err(ParseError::new(ParseErrorKind::DuplicateColumn(name), bom + names[index].offset, None, &names[index].value).at_line(1));
             }
-        },
-        Err(e) => Err(e),
+        }
+
+        let missing = |name| ParseError::new(ParseErrorKind::MissingColumn(name), 0, None, "").at_line(1);
+
+        Ok(Columns {
+            action:     action.ok_or_else(|| missing(TYPE))?,
+            client_id:  client_id.ok_or_else(|| missing(CLIENT))?,
+            tx_id:      tx_id.ok_or_else(|| missing(TX))?,
+            tx_amount:  tx_amount.ok_or_else(|| missing(AMOUNT))?,
+
+            count:      names.len(),
+        })
     }
 }
 
-fn parse_transaction_header(input: &str) -> IResult<&str, (ClientID, TxID)> {
-    match nom::sequence::tuple((
-        parse_client_id,
-        parse_tx_id,
-    ))(input) {
-        Ok((input, (
-            client_id,
-            tx_id,
-        ))) => {
-            Ok((input, (client_id, tx_id)))
+impl Default for Columns {
+    /// Column map of the canonical header "type, client, tx, amount"
+    fn default() -> Self {
+        Columns {
+            action:     0,
+            client_id:  1,
+            tx_id:      2,
+            tx_amount:  3,
+
+            count:      4,
         }
-        Err(e) => Err(e),
     }
 }
 
-fn parse_transaction_complete(input: &str) -> IResult<&str, (ClientID, TxID, TxAmount)> {
-    match nom::sequence::tuple((
-        parse_transaction_header,
-        parse_tx_amount,
-    ))(input) {
-        Ok((input, (
-            (client_id, tx_id),
-            tx_amount,
-        ))) => {
-            Ok((input, (client_id, tx_id, tx_amount)))
+/// Handling of amounts with more fractional digits than `TxAmount` can represent
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ExcessPrecision {
+    /// The row is rejected
+    #[default]
+    Reject,
+    /// The amount is rounded according to the rounding mode
+    Round(RoundingMode),
+    /// The excess digits are dropped
+    Truncate,
+}
+
+impl FromStr for ExcessPrecision {
+    type Err = &'static str;
+
+    /// Parses "reject", "truncate", "round" (half-even) or `round:<mode>`, see `RoundingMode`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(ExcessPrecision::Reject),
+            "truncate" => Ok(ExcessPrecision::Truncate),
+            "round" => Ok(ExcessPrecision::Round(RoundingMode::HalfEven)),
+            _ => match s.strip_prefix("round:") {
+                Some(mode) => mode.parse().map(ExcessPrecision::Round),
+                None => Err("Unknown excess precision policy, expected reject, truncate, round or round:<mode>"),
+            },
         }
-        Err(e) => Err(e),
     }
 }
 
-fn parse_withdrawal(input: &str) -> IResult<&str, LedgerItem> {
-    let (input, _) = tag("withdrawal")(input)?;
-
-    let (input, (client_id, tx_id, tx_amount)) = parse_transaction_complete(input)?;
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum ActionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
 
-    Ok((input, LedgerItem { client_id, tx_id, action: LedgerAction::Withdrawal(tx_amount) }))
+/// A single field of a row together with its byte offset in the line
+pub(crate) struct Field<'a> {
+    pub(crate) offset:  usize,
+    pub(crate) value:   Cow<'a, str>,
 }
 
-fn parse_deposit(input: &str) -> IResult<&str, LedgerItem> {
-    let (input, _) = tag("deposit")(input)?;
+fn parse_action_type(input: &str) -> IResult<&str, ActionType> {
+    delimited(
+        space0,
+        alt((
+            map(tag_no_case("withdrawal"), |_| ActionType::Withdrawal),
+            map(tag_no_case("deposit"), |_| ActionType::Deposit),
+            map(tag_no_case("dispute"), |_| ActionType::Dispute),
+            map(tag_no_case("chargeback"), |_| ActionType::Chargeback),
+            map(tag_no_case("resolve"), |_| ActionType::Resolve),
+        )),
+        space0,
+    )(input)
+}
 
-    let (input, (client_id, tx_id, tx_amount)) = parse_transaction_complete(input)?;
+fn parse_id(input: &str) -> IResult<&str, &str> {
+    delimited(space0, digit1, space0)(input)
+}
 
-    Ok((input, LedgerItem { client_id, tx_id, action: LedgerAction::Deposit(tx_amount) }))
+/// Parses an unsigned decimal number into its integral and fractional digits
+///
+/// Accepts "1.5", "1", "1." and ".5"
+fn parse_decimal(input: &str) -> IResult<&str, (&str, &str)> {
+    alt((
+        pair(digit1, map(opt(preceded(char('.'), digit0)), Option::unwrap_or_default)),
+        map(preceded(char('.'), digit1), |fractional| ("", fractional)),
+    ))(input)
 }
 
-fn parse_dispute(input: &str) -> IResult<&str, LedgerItem> {
-    let (input, _) = tag("dispute")(input)?;
+fn parse_tx_amount(input: &str) -> IResult<&str, (&str, &str)> {
+    delimited(space0, parse_decimal, space0)(input)
+}
 
-    let (input, (client_id, tx_id)) = parse_transaction_header(input)?;
+/// Parses a field enclosed in double quotes, a double quote inside the field is escaped as ""
+fn parse_quoted_field(input: &str) -> IResult<&str, Cow<'_, str>> {
+    preceded(
+        pair(space0, char('"')),
+        cut(map(
+            terminated(
+                fold_many0(
+                    alt((is_not("\""), map(tag("\"\""), |_| "\""))),
+                    String::new(),
+                    |mut field, part| {
+                        field.push_str(part);
+                        field
+                    },
+                ),
+                pair(char('"'), space0),
+            ),
+            Cow::Owned,
+        )),
+    )(input)
+}
 
-    Ok((input, LedgerItem { client_id, tx_id, action: LedgerAction::Dispute }))
+fn parse_plain_field(input: &str) -> IResult<&str, Cow<'_, str>> {
+    map(take_till(|c| c == ','), Cow::Borrowed)(input)
 }
 
-fn parse_resolve(input: &str) -> IResult<&str, LedgerItem> {
-    let (input, _) = tag("resolve")(input)?;
+/// Splits a row into its fields
+///
+/// A trailing carriage return (CRLF line ending) is ignored
+fn parse_record(input: &str) -> Result<Vec<Field<'_>>, ParseError> {
+    let line = input.strip_suffix('\r').unwrap_or(input);
+
+    let mut fields = Vec::new();
+    let mut rest = line;
+
+    loop {
+        let offset = line.len() - rest.len();
+
+        let (remaining, value) = alt((parse_quoted_field, parse_plain_field))(rest)
+            .map_err(|_| ParseError::new(ParseErrorKind::MalformedField, offset, None, rest))?;
 
-    let (input, (client_id, tx_id)) = parse_transaction_header(input)?;
+        fields.push(Field { offset, value });
+
+        if remaining.is_empty() {
+            return Ok(fields);
+        } else if let Some(remaining) = remaining.strip_prefix(',') {
+            rest = remaining;
+        } else {
+            let field = &rest[..rest.len() - remaining.len()];
+
+            return Err(ParseError::new(ParseErrorKind::MalformedField, offset, None, field));
+        }
+    }
+}
+
+fn parse_field<'a, O>(parser: impl FnMut(&'a str) -> IResult<&'a str, O>, field: &'a str) -> Option<O> {
+    all_consuming(parser)(field)
+        .map(|(_, value)| value)
+        .ok()
+}
 
-    Ok((input, LedgerItem { client_id, tx_id, action: LedgerAction::Resolve }))
+/// Decodes a single row according to the column map of the header
+///
+/// Amounts with more fractional digits than `TxAmount` can represent are rejected.
+/// The line number of the returned error is not set, see `ParseError::at_line`
+pub fn parse_line(columns: &Columns, input: &str) -> Result<LedgerItem, ParseError> {
+    parse_line_with(columns, ExcessPrecision::Reject, input).map(|(item, _)| item)
 }
 
-fn parse_chargeback(input: &str) -> IResult<&str, LedgerItem> {
-    let (input, _) = tag("chargeback")(input)?;
+/// Decodes a single row according to the column map of the header
+///
+/// Amounts with more fractional digits than `TxAmount` can represent are handled according to excess_precision.
+/// Returns the item and whether its amount had to be rounded or truncated.
+/// The line number of the returned error is not set, see `ParseError::at_line`
+pub fn parse_line_with(columns: &Columns, excess_precision: ExcessPrecision, input: &str) -> Result<(LedgerItem, bool), ParseError> {
+    let fields = parse_record(input)?;
 
-    let (input, (client_id, tx_id)) = parse_transaction_header(input)?;
+    if let Some(field) = fields.iter().skip(columns.count).find(|field| !field.value.trim().is_empty()) {
+        return Err(ParseError::new(ParseErrorKind::UnexpectedField, field.offset, None, &field.value));
+    }
+
+    let field = |column| {
+        let index = match column {
+            TYPE => columns.action,
+            CLIENT => columns.client_id,
+            TX => columns.tx_id,
+            _ => columns.tx_amount,
+        };
 
-    Ok((input, LedgerItem { client_id, tx_id, action: LedgerAction::Chargeback }))
+        fields.get(index)
+    };
+
+    decode_fields(field, input.len(), excess_precision)
 }
 
-fn parse_internal(input: &str) -> IResult<&str, LedgerItem> {
-    alt((parse_withdrawal, parse_deposit, parse_dispute, parse_chargeback, parse_resolve))(input)
+/// Validates the fields of a row and decodes them into an item
+///
+/// `field` returns the field of a column (type, client, tx or amount) or `None` if the row lacks it,
+/// `end` is the offset reported for missing fields. Shared by all input formats.
+pub(crate) fn decode_fields<'a>(
+    field:              impl Fn(&'static str) -> Option<&'a Field<'a>>,
+    end:                usize,
+    excess_precision:   ExcessPrecision,
+) -> Result<(LedgerItem, bool), ParseError> {
+    let field = |column: &'static str| {
+        field(column)
+            .map(|field| (field.offset, column, field.value.as_ref()))
+            .ok_or_else(|| ParseError::new(ParseErrorKind::MissingField, end, Some(column), ""))
+    };
+
+    let error = |kind, (offset, column, value): (usize, &'static str, &str)| ParseError::new(kind, offset, Some(column), value);
+
+    let action_field = field(TYPE)?;
+    let action_type = parse_field(parse_action_type, action_field.2)
+        .ok_or_else(|| error(ParseErrorKind::UnknownType, action_field))?;
+
+    let client_id_field = field(CLIENT)?;
+    let client_id = parse_field(parse_id, client_id_field.2)
+        .ok_or_else(|| error(ParseErrorKind::InvalidClientId, client_id_field))?
+        .parse::<ClientID>()
+        .map_err(|_| error(ParseErrorKind::Overflow, client_id_field))?;
+
+    let tx_id_field = field(TX)?;
+    let tx_id = parse_field(parse_id, tx_id_field.2)
+        .ok_or_else(|| error(ParseErrorKind::InvalidTxId, tx_id_field))?
+        .parse::<TxID>()
+        .map_err(|_| error(ParseErrorKind::Overflow, tx_id_field))?;
+
+    let tx_amount = || {
+        let tx_amount_field = field(AMOUNT)?;
+        let tx_amount = parse_field(parse_tx_amount, tx_amount_field.2)
+            .ok_or_else(|| error(ParseErrorKind::InvalidAmount, tx_amount_field))?;
+
+        let tx_amount = match excess_precision {
+            ExcessPrecision::Reject => TxAmount::try_from(tx_amount).map(|tx_amount| (tx_amount, false)),
+            ExcessPrecision::Round(mode) => TxAmount::try_from_rounded(tx_amount, mode),
+            ExcessPrecision::Truncate => TxAmount::try_from_rounded(tx_amount, RoundingMode::TowardZero),
+        };
+
+        tx_amount.map_err(|e| match e {
+            FpParseError::ExcessPrecision => error(ParseErrorKind::TooManyDecimals, tx_amount_field),
+            FpParseError::Overflow => error(ParseErrorKind::Overflow, tx_amount_field),
+            FpParseError::InvalidDigit => error(ParseErrorKind::InvalidAmount, tx_amount_field),
+        })
+    };
+
+    // Disputes and chargebacks carry an amount only if they are partial, the field can be empty or missing
+    let partial_tx_amount = || match field(AMOUNT) {
+        Ok((_, _, value)) if !value.trim().is_empty() => tx_amount().map(|(tx_amount, adjusted)| (Some(tx_amount), adjusted)),
+        _ => Ok((None, false)),
+    };
+
+    let (action, adjusted) = match action_type {
+        ActionType::Deposit => tx_amount().map(|(tx_amount, adjusted)| (LedgerAction::Deposit(tx_amount), adjusted))?,
+        ActionType::Withdrawal => tx_amount().map(|(tx_amount, adjusted)| (LedgerAction::Withdrawal(tx_amount), adjusted))?,
+        ActionType::Dispute => partial_tx_amount().map(|(tx_amount, adjusted)| (LedgerAction::Dispute(tx_amount), adjusted))?,
+        ActionType::Resolve => (LedgerAction::Resolve, false),
+        ActionType::Chargeback => partial_tx_amount().map(|(tx_amount, adjusted)| (LedgerAction::Chargeback(tx_amount), adjusted))?,
+    };
+
+    if LedgerAction::Resolve == action {
+        if let Some(tx_amount_field) = field(AMOUNT).ok().filter(|(_, _, value)| !value.trim().is_empty()) {
+            return Err(error(ParseErrorKind::UnexpectedAmount, tx_amount_field));
+        }
+    }
+
+    Ok((LedgerItem { client_id, tx_id, action }, adjusted))
 }
 
-pub fn parse_line(input: &str) -> Result<LedgerItem, &'static str> {
-    if let Ok((_input, item)) = parse_internal(input) {
-        Ok(item)
-    } else {
-        Err("Invalid item")
+/// Formats an item as a row matching the default column map, the inverse of `parse_line`
+pub fn format_line(item: &LedgerItem) -> String {
+    match item.action {
+        LedgerAction::Deposit(amount) => format!("deposit, {}, {}, {}", item.client_id, item.tx_id, amount),
+        LedgerAction::Withdrawal(amount) => format!("withdrawal, {}, {}, {}", item.client_id, item.tx_id, amount),
+        LedgerAction::Dispute(Some(amount)) => format!("dispute, {}, {}, {}", item.client_id, item.tx_id, amount),
+        LedgerAction::Dispute(None) => format!("dispute, {}, {},", item.client_id, item.tx_id),
+        LedgerAction::Resolve => format!("resolve, {}, {},", item.client_id, item.tx_id),
+        LedgerAction::Chargeback(Some(amount)) => format!("chargeback, {}, {}, {}", item.client_id, item.tx_id, amount),
+        LedgerAction::Chargeback(None) => format!("chargeback, {}, {},", item.client_id, item.tx_id),
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::*;
-    use super::parse_line;
+    use crate::error::ParseErrorKind;
+    use crate::fp_isize::RoundingMode;
+    use super::{Columns, ExcessPrecision, format_line, parse_line, parse_line_with};
 
     #[test]
     fn deposit() {
-        let tx = parse_line("deposit, 1, 1, 1.10").unwrap();
+        let tx = parse_line(&Columns::default(), "deposit, 1, 1, 1.10").unwrap();
 
         assert_eq!(tx.client_id, 1);
         assert_eq!(tx.tx_id, 1);
-        assert_eq!(tx.action, LedgerAction::Deposit(FpIsize::new(11000)));
+        assert_eq!(tx.action, LedgerAction::Deposit(TxAmount::new(11000)));
     }
 
     #[test]
     fn withdrawal() {
-        let tx = parse_line("withdrawal, 1, 1, 1.10").unwrap();
+        let tx = parse_line(&Columns::default(), "withdrawal, 1, 1, 1.10").unwrap();
 
         assert_eq!(tx.client_id, 1);
         assert_eq!(tx.tx_id, 1);
-        assert_eq!(tx.action, LedgerAction::Withdrawal(FpIsize::new(11000)));
+        assert_eq!(tx.action, LedgerAction::Withdrawal(TxAmount::new(11000)));
     }
 
     #[test]
     fn dispute() {
-        let tx = parse_line("dispute, 1, 1").unwrap();
+        let tx = parse_line(&Columns::default(), "dispute, 1, 1").unwrap();
 
         assert_eq!(tx.client_id, 1);
         assert_eq!(tx.tx_id, 1);
-        assert_eq!(tx.action, LedgerAction::Dispute);
+        assert_eq!(tx.action, LedgerAction::Dispute(None));
+
+        let tx = parse_line(&Columns::default(), "dispute, 1, 1, 0.5").unwrap();
+
+        assert_eq!(tx.action, LedgerAction::Dispute(Some(TxAmount::new(5000))));
     }
 
     #[test]
     fn resolve() {
-        let tx = parse_line("resolve, 1, 1").unwrap();
+        let tx = parse_line(&Columns::default(), "resolve, 1, 1").unwrap();
 
         assert_eq!(tx.client_id, 1);
         assert_eq!(tx.tx_id, 1);
@@ -191,10 +396,215 @@ mod test {
 
     #[test]
     fn chargeback() {
-        let tx = parse_line("chargeback, 1, 1").unwrap();
+        let tx = parse_line(&Columns::default(), "chargeback, 1, 1").unwrap();
 
         assert_eq!(tx.client_id, 1);
         assert_eq!(tx.tx_id, 1);
-        assert_eq!(tx.action, LedgerAction::Chargeback);
+        assert_eq!(tx.action, LedgerAction::Chargeback(None));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn header() {
+        assert_eq!(Columns::parse_header("type, client, tx, amount"), Ok(Columns::default()));
+        assert_eq!(Columns::parse_header("type,client,tx,amount"), Ok(Columns::default()));
+
+        assert_eq!(Columns::parse_header("type, client, amount").unwrap_err().kind, ParseErrorKind::MissingColumn("tx"));
+
+        let error = Columns::parse_header("type, client, tx, TX, amount").unwrap_err();
+
+        assert_eq!(error.kind, ParseErrorKind::DuplicateColumn("tx"));
+        assert_eq!((error.line, error.offset), (1, 17));
+    }
+
+    #[test]
+    fn reordered_columns() {
+        let columns = Columns::parse_header("amount, tx, client, type").unwrap();
+
+        let tx = parse_line(&columns, "1.10, 2, 1, deposit").unwrap();
+
+        assert_eq!(tx.client_id, 1);
+        assert_eq!(tx.tx_id, 2);
+        assert_eq!(tx.action, LedgerAction::Deposit(TxAmount::new(11000)));
+
+        let tx = parse_line(&columns, ", 2, 1, dispute").unwrap();
+
+        assert_eq!(tx.client_id, 1);
+        assert_eq!(tx.tx_id, 2);
+        assert_eq!(tx.action, LedgerAction::Dispute(None));
+
+        let tx = parse_line(&Columns::default(), "dispute, 1, 1, 0.5").unwrap();
+
+        assert_eq!(tx.action, LedgerAction::Dispute(Some(TxAmount::new(5000))));
+    }
+
+    #[test]
+    fn extra_columns() {
+        let columns = Columns::parse_header("date, type, client, reference, tx, amount, currency").unwrap();
+
+        let tx = parse_line(&columns, "2021-08-01, withdrawal, 3, abc, 4, 2.5, EUR").unwrap();
+
+        assert_eq!(tx.client_id, 3);
+        assert_eq!(tx.tx_id, 4);
+        assert_eq!(tx.action, LedgerAction::Withdrawal(TxAmount::new(25000)));
+
+        assert!(parse_line(&columns, "2021-08-01, withdrawal, 3, abc, 4, 2.5, EUR, extra").is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_line(&Columns::default(), "deposit, 1, 1").is_err());
+        assert!(parse_line(&Columns::default(), "resolve, 1, 1, 1.0").is_err());
+        assert!(parse_line(&Columns::default(), "transfer, 1, 1, 1.0").is_err());
+        assert!(parse_line(&Columns::default(), "deposit, 1, x, 1.0").is_err());
+    }
+
+    #[test]
+    fn relaxed_format() {
+        let columns = Columns::default();
+
+        let tx = parse_line(&columns, "deposit,1,2,1.0").unwrap();
+        assert_eq!((tx.client_id, tx.tx_id, tx.action), (1, 2, LedgerAction::Deposit(TxAmount::new(10000))));
+
+        let tx = parse_line(&columns, "deposit, 1, 2, 5").unwrap();
+        assert_eq!(tx.action, LedgerAction::Deposit(TxAmount::new(50000)));
+
+        let tx = parse_line(&columns, "deposit, 1, 2, .5").unwrap();
+        assert_eq!(tx.action, LedgerAction::Deposit(TxAmount::new(5000)));
+
+        let tx = parse_line(&columns, "withdrawal,\t1,\t2,\t5.\r").unwrap();
+        assert_eq!(tx.action, LedgerAction::Withdrawal(TxAmount::new(50000)));
+
+        let tx = parse_line(&columns, "dispute, 1, 2,").unwrap();
+        assert_eq!((tx.client_id, tx.tx_id, tx.action), (1, 2, LedgerAction::Dispute(None)));
+
+        let tx = parse_line(&columns, "resolve, 1, 2,\r").unwrap();
+        assert_eq!(tx.action, LedgerAction::Resolve);
+
+        let tx = parse_line(&columns, "deposit, 1, 2, 1.5,").unwrap();
+        assert_eq!(tx.action, LedgerAction::Deposit(TxAmount::new(15000)));
+
+        let tx = parse_line(&columns, "\"deposit\", \"1\", \"2\", \"1.5\"").unwrap();
+        assert_eq!((tx.client_id, tx.tx_id, tx.action), (1, 2, LedgerAction::Deposit(TxAmount::new(15000))));
+
+        let tx = parse_line(&columns, "Chargeback, 1, 2").unwrap();
+        assert_eq!(tx.action, LedgerAction::Chargeback(None));
+    }
+
+    #[test]
+    fn quoted_header() {
+        let columns = Columns::parse_header("\u{feff}\"Type\",\"Client\",\"Tx\",\"Amount\",\"Note, \"\"free text\"\"\"\r").unwrap();
+
+        let tx = parse_line(&columns, "deposit, 1, 2, 1.5, \"a, \"\"quoted\"\" note\"").unwrap();
+        assert_eq!(tx.action, LedgerAction::Deposit(TxAmount::new(15000)));
+    }
+
+    #[test]
+    fn malformed() {
+        let columns = Columns::default();
+
+        assert!(parse_line(&columns, "").is_err());
+        assert!(parse_line(&columns, "deposit, 1, 2, 1.5.1").is_err());
+        assert!(parse_line(&columns, "deposit, 1, 2, -1.5").is_err());
+        assert!(parse_line(&columns, "deposit, 1, 2, 1 5").is_err());
+        assert!(parse_line(&columns, "deposit, 1, 2, .").is_err());
+        assert!(parse_line(&columns, "deposit, 1, 2, ").is_err());
+        assert!(parse_line(&columns, "deposit, 1, 2, \"1.5").is_err());
+        assert!(parse_line(&columns, "deposit, 1, 2, \"1.5\"x").is_err());
+        assert!(parse_line(&columns, "deposit, 1, 2, 1.5, x").is_err());
+        assert!(parse_line(&columns, "deposits, 1, 2, 1.5").is_err());
+        assert!(parse_line(&columns, "deposit, 65536, 2, 1.5").is_err());
+        assert!(parse_line(&columns, "deposit\r, 1, 2, 1.5").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let columns = Columns::default();
+
+        let error = parse_line(&columns, "transfer, 1, 2, 1.5").unwrap_err();
+        assert_eq!((error.kind, error.offset, error.column, error.field.as_str()), (ParseErrorKind::UnknownType, 0, Some("type"), "transfer"));
+
+        let error = parse_line(&columns, "deposit, x, 2, 1.5").unwrap_err();
+        assert_eq!((error.kind, error.offset, error.column, error.field.as_str()), (ParseErrorKind::InvalidClientId, 8, Some("client"), " x"));
+
+        let error = parse_line(&columns, "deposit, 70000, 2, 1.5").unwrap_err();
+        assert_eq!((error.kind, error.column), (ParseErrorKind::Overflow, Some("client")));
+
+        let error = parse_line(&columns, "deposit, 1, -2, 1").unwrap_err();
+        assert_eq!((error.kind, error.column), (ParseErrorKind::InvalidTxId, Some("tx")));
+
+        let error = parse_line(&columns, "deposit, 1, 5000000000, 1").unwrap_err();
+        assert_eq!((error.kind, error.column), (ParseErrorKind::Overflow, Some("tx")));
+
+        let error = parse_line(&columns, "deposit, 1, 2, 1.23456").unwrap_err();
+        assert_eq!((error.kind, error.offset, error.column, error.field.as_str()), (ParseErrorKind::TooManyDecimals, 14, Some("amount"), " 1.23456"));
+
+        let error = parse_line(&columns, "deposit, 1, 2, 999999999999999999999999999999999999999999999.5").unwrap_err();
+        assert_eq!((error.kind, error.column), (ParseErrorKind::Overflow, Some("amount")));
+
+        let error = parse_line(&columns, "deposit, 1, 2, abc").unwrap_err();
+        assert_eq!((error.kind, error.column), (ParseErrorKind::InvalidAmount, Some("amount")));
+
+        let error = parse_line(&columns, "deposit, 1, 2").unwrap_err();
+        assert_eq!((error.kind, error.offset, error.column), (ParseErrorKind::MissingField, 13, Some("amount")));
+
+        let error = parse_line(&columns, "resolve, 1, 2, 1.5").unwrap_err();
+        assert_eq!((error.kind, error.column), (ParseErrorKind::UnexpectedAmount, Some("amount")));
+
+        let error = parse_line(&columns, "dispute, 1, 2, abc").unwrap_err();
+        assert_eq!((error.kind, error.column), (ParseErrorKind::InvalidAmount, Some("amount")));
+
+        let error = parse_line(&columns, "deposit, 1, 2, 1.5, x").unwrap_err();
+        assert_eq!((error.kind, error.offset), (ParseErrorKind::UnexpectedField, 19));
+
+        let error = parse_line(&columns, "deposit, 1, \"2\"x, 1.5").unwrap_err();
+        assert_eq!((error.kind, error.offset), (ParseErrorKind::MalformedField, 11));
+
+        assert_eq!(error.at_line(7).to_string(), "[Line 7, byte 11] malformed field");
+        assert_eq!(
+            parse_line(&columns, "deposit, 1, 2, 1.23456").unwrap_err().at_line(3).to_string(),
+            "[Line 3, byte 14] Field amount (\" 1.23456\"): amount has too many decimals",
+        );
+    }
+
+    #[test]
+    fn excess_precision() {
+        let columns = Columns::default();
+        let row = "deposit, 1, 2, 1.23456";
+
+        assert_eq!(parse_line_with(&columns, ExcessPrecision::Reject, row).unwrap_err().kind, ParseErrorKind::TooManyDecimals);
+
+        let (tx, adjusted) = parse_line_with(&columns, ExcessPrecision::Truncate, row).unwrap();
+        assert_eq!((tx.action, adjusted), (LedgerAction::Deposit(TxAmount::new(12345)), true));
+
+        let (tx, adjusted) = parse_line_with(&columns, ExcessPrecision::Round(RoundingMode::HalfEven), row).unwrap();
+        assert_eq!((tx.action, adjusted), (LedgerAction::Deposit(TxAmount::new(12346)), true));
+
+        let (tx, adjusted) = parse_line_with(&columns, ExcessPrecision::Round(RoundingMode::HalfEven), "withdrawal, 1, 2, 1.23450").unwrap();
+        assert_eq!((tx.action, adjusted), (LedgerAction::Withdrawal(TxAmount::new(12345)), false));
+
+        let (tx, adjusted) = parse_line_with(&columns, ExcessPrecision::Truncate, "dispute, 1, 2").unwrap();
+        assert_eq!((tx.action, adjusted), (LedgerAction::Dispute(None), false));
+
+        let (tx, adjusted) = parse_line_with(&columns, ExcessPrecision::Truncate, "chargeback, 1, 2, 0.123456").unwrap();
+        assert_eq!((tx.action, adjusted), (LedgerAction::Chargeback(Some(TxAmount::new(1234))), true));
+
+        assert_eq!("reject".parse(), Ok(ExcessPrecision::Reject));
+        assert_eq!("truncate".parse(), Ok(ExcessPrecision::Truncate));
+        assert_eq!("round".parse(), Ok(ExcessPrecision::Round(RoundingMode::HalfEven)));
+        assert_eq!("round:half-up".parse(), Ok(ExcessPrecision::Round(RoundingMode::HalfUp)));
+        assert!("round:nearest".parse::<ExcessPrecision>().is_err());
+        assert!("drop".parse::<ExcessPrecision>().is_err());
+    }
+
+    #[test]
+    fn format() {
+        let columns = Columns::default();
+
+        for row in [
+            "deposit, 1, 2, 1.5000", "withdrawal, 3, 4, 0.0001", "dispute, 1, 2,", "dispute, 1, 2, 0.5000", "resolve, 1, 2,",
+            "chargeback, 1, 2,", "chargeback, 1, 2, 0.2500",
+        ] {
+            assert_eq!(format_line(&parse_line(&columns, row).unwrap()), row);
+        }
+    }
+}
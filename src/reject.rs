@@ -0,0 +1,138 @@
+use std::{path::Path, sync::Arc};
+
+use tokio::{fs::OpenOptions, io::{self, AsyncWriteExt, BufWriter}, sync::mpsc::{Sender, channel}, task::JoinHandle};
+
+use crate::error::{ParseError, ProcessorError};
+
+/// Location of a row in the input, kept to report the row when it is rejected
+#[derive(Debug, Clone)]
+pub struct Origin {
+    pub file:   Arc<str>,
+    pub line:   usize,
+    pub row:    String,
+}
+
+/// A row which could not be parsed or was refused by the account manager
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    pub file:       Arc<str>,
+    pub line:       usize,
+    pub row:        String,
+    pub code:       &'static str,
+    pub message:    String,
+}
+
+impl Rejection {
+    pub fn parse(origin: Origin, error: &ParseError) -> Self {
+        Rejection {
+            file:       origin.file,
+            line:       origin.line,
+            row:        origin.row,
+            code:       error.kind.code(),
+            message:    error.to_string(),
+        }
+    }
+
+    pub fn processor(origin: Origin, error: &ProcessorError) -> Self {
+        Rejection {
+            file:       origin.file,
+            line:       origin.line,
+            row:        origin.row,
+            code:       error.code(),
+            message:    error.to_string(),
+        }
+    }
+}
+
+/// Quotes a CSV field, double quotes inside the field are escaped as ""
+fn quote(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// Writes rejected rows to a CSV file with the columns file, line, code, message and row
+///
+/// The rows are written by a separate task, rejections are submitted through the senders returned by `sender`.
+/// The file is complete once all senders are dropped and `join` returned.
+///
+/// A run recovering from a journal appends to the file, the rows refused before the crash are not reported again
+/// as the journal skips them.
+pub struct RejectLog {
+    handle: JoinHandle<io::Result<()>>,
+    sender: Sender<Rejection>,
+}
+
+impl RejectLog {
+    /// Creates the file, or appends to it if `append` is set, the header is only written to an empty file
+    pub async fn create(path: impl AsRef<Path>, append: bool) -> io::Result<Self> {
+        let file = OpenOptions::new().write(true).create(true).append(append).truncate(!append).open(path).await?;
+        let empty = file.metadata().await?.len() == 0;
+
+        let mut writer = BufWriter::new(file);
+        let (sender, mut receiver) = channel::<Rejection>(128);
+
+        let handle = tokio::spawn(async move {
+            if empty {
+                writer.write_all(b"file,line,code,message,row\n").await?;
+            }
+
+            let mut next = receiver.recv().await;
+
+            while let Some(rejection) = next {
+                let line = format!("{},{},{},{},{}\n",
+                    quote(&rejection.file), rejection.line, rejection.code, quote(&rejection.message), quote(&rejection.row),
+                );
+
+                writer.write_all(line.as_bytes()).await?;
+
+                next = match receiver.try_recv() {
+                    Ok(rejection) => Some(rejection),
+                    // The rows are written to the file before waiting for more, so they survive a failed run
+                    Err(_) => {
+                        writer.flush().await?;
+
+                        receiver.recv().await
+                    },
+                };
+            }
+
+            writer.flush().await
+        });
+
+        Ok(RejectLog {
+            handle,
+            sender,
+        })
+    }
+
+    pub fn sender(&self) -> Sender<Rejection> {
+        self.sender.clone()
+    }
+
+    /// Submits a rejection, fails if the writing task stopped, `join` returns its error then
+    pub async fn reject(&self, rejection: Rejection) -> io::Result<()> {
+        self.sender.send(rejection).await.map_err(|_| closed())
+    }
+
+    /// Waits for the writing task to write all submitted rejections, fails if writing the file failed
+    pub async fn join(self) -> io::Result<()> {
+        drop(self.sender);
+
+        self.handle.await.map_err(io::Error::other)?
+    }
+}
+
+/// Error of a rejection which cannot be submitted as the writing task stopped
+pub fn closed() -> io::Error {
+    io::Error::other("The rejects log stopped")
+}
+
+#[cfg(test)]
+mod test {
+    use super::quote;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("deposit, 1, 1, 1.0"), "\"deposit, 1, 1, 1.0\"");
+        assert_eq!(quote("\"deposit\", 1"), "\"\"\"deposit\"\", 1\"");
+    }
+}
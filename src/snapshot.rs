@@ -0,0 +1,614 @@
+//! Point-in-time snapshot of all accounts
+//!
+//! A snapshot is a versioned text file holding the policy the accounts were processed with and every account
+//! with its balances, lock state, statistics and the transactions which can still be disputed, resolved or charged back:
+//!
+//! ```text
+//! transaction-processor snapshot 6
+//! policy, <dispute policy>, <max dispute cycles>
+//! journal, <run>, <seq of every shard>
+//! account, <client>, <active|locked>, <available>, <held>, <lock tx>, <deposits>, <withdrawals>, <disputes>, <resolves>, <chargebacks>, <last tx>
+//! tx, <tx>, <amount>, <state>, <history>, <disputed>, <charged back>
+//! ```
+//!
+//! The `journal` line is only written for a snapshot taken during a run with a journal, the sequence numbers
+//! are separated by spaces. The `tx` lines belong to the preceding `account` line, withdrawals are stored with negative amounts.
+//! The lock and last transaction are empty if there is none. The history lists the states the transaction
+//! went through separated by spaces, e.g. `Disputed Resolved Disputed`, and is empty for a new transaction.
+//! The disputed and charged back amounts are signed like the amount and zero outside of a dispute or chargeback.
+//!
+//! Version 1 snapshots, whose account lines end after `<held>`, can still be read. The lock reason and
+//! statistics are derived from the transactions then, the last activity is unknown. The `tx` lines of
+//! version 1 and 2 snapshots end after `<state>`, their transactions went through at most one dispute cycle.
+//! Up to version 3 the `tx` lines end after `<history>` and disputes and chargebacks cover the whole amount.
+//! Snapshots before version 5 have no `policy` line, their accounts are taken as processed with the default policy.
+//! Snapshots before version 6 have no `journal` line.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ClientID, TxAmount, TxID,
+    account::{Account, LockReason, TransactionCounts},
+    journal::JournalPosition,
+    policy::Policy,
+    transaction::{Transaction, TransactionState},
+};
+
+const MAGIC: &str = "transaction-processor snapshot";
+const VERSION: u32 = 6;
+
+/// Accounts of all clients at a point in time
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    /// Policy the open disputes were created with, a run restoring the snapshot has to use the same dispute policy
+    pub policy: Policy,
+    /// Items of the journal of the run which took the snapshot, the journal is only replayed after them
+    pub journal: Option<JournalPosition>,
+    pub accounts: BTreeMap<ClientID, Account>,
+}
+
+/// Fields of an account line, the transactions follow on the next lines
+struct AccountLine {
+    client_id:      ClientID,
+    locked:         bool,
+    available:      TxAmount,
+    held:           TxAmount,
+    /// Only known since version 2
+    lock_tx:        Option<TxID>,
+    counts:         Option<TransactionCounts>,
+    last_activity:  Option<TxID>,
+}
+
+impl AccountLine {
+    fn into_account(self, line: usize, transactions: BTreeMap<TxID, Transaction>) -> io::Result<(ClientID, Account)> {
+        if self.available.checked_add(self.held).is_none() {
+            return Err(invalid(line, "total funds out of range"));
+        }
+
+        let charged_back = || transactions.iter()
+            .find(|(_, transaction)| TransactionState::Chargeback == transaction.state())
+            .map(|(tx_id, _)| *tx_id);
+
+        let lock_reason = match (self.locked, self.lock_tx.or_else(charged_back)) {
+            (false, _) => None,
+            (true, Some(tx_id)) => Some(LockReason::Chargeback(tx_id)),
+            (true, None) => return Err(invalid(line, "locked account without a chargeback")),
+        };
+
+        let counts = self.counts.unwrap_or_else(|| {
+            let mut counts = TransactionCounts::default();
+
+            for transaction in transactions.values() {
+                if transaction.amount() < TxAmount::zero() {
+                    counts.withdrawals += 1;
+                } else {
+                    counts.deposits += 1;
+                }
+
+                match transaction.state() {
+                    TransactionState::New => {},
+                    TransactionState::Disputed => counts.disputes += 1,
+                    TransactionState::Resolved => {
+                        counts.disputes += 1;
+                        counts.resolves += 1;
+                    },
+                    TransactionState::Chargeback => {
+                        counts.disputes += 1;
+                        counts.chargebacks += 1;
+                    },
+                }
+            }
+
+            counts
+        });
+
+        let account = Account::restore(lock_reason, self.available, self.held, transactions, counts, self.last_activity);
+
+        Ok((self.client_id, account))
+    }
+}
+
+impl Snapshot {
+
+    /// Snapshot without accounts for a run with the policy
+    pub fn new(policy: Policy) -> Self {
+        Snapshot {
+            policy,
+            journal: None,
+            accounts: BTreeMap::new(),
+        }
+    }
+
+    /// Writes the snapshot atomically, the file either contains the previous or the new snapshot
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let temporary = temporary_path(path);
+
+        let written = File::create(&temporary).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+
+            self.write_records(&mut writer)?;
+
+            writer.flush()
+        });
+
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temporary);
+
+            return Err(e);
+        }
+
+        replace_file(&temporary, path)
+    }
+
+    fn write_records(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "{} {}", MAGIC, VERSION)?;
+        writeln!(writer, "policy, {}, {}", self.policy.dispute, self.policy.max_dispute_cycles)?;
+
+        if let Some(position) = &self.journal {
+            let seqs = position.seqs.iter().map(u64::to_string).collect::<Vec<_>>();
+
+            writeln!(writer, "journal, {}, {}", position.run, seqs.join(" "))?;
+        }
+
+        let optional = |tx_id: Option<TxID>| tx_id.map(|tx_id| tx_id.to_string()).unwrap_or_default();
+
+        for (client_id, account) in self.accounts.iter() {
+            let (state, lock_tx) = match account.lock_reason() {
+                Some(LockReason::Chargeback(tx_id)) => ("locked", Some(tx_id)),
+                None => ("active", None),
+            };
+
+            let counts = account.counts();
+
+            writeln!(writer, "account, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+                client_id, state, account.available(), account.held(), optional(lock_tx),
+                counts.deposits, counts.withdrawals, counts.disputes, counts.resolves, counts.chargebacks,
+                optional(account.last_activity()),
+            )?;
+
+            for (tx_id, transaction) in account.transactions() {
+                let history = transaction.history().iter().map(TransactionState::to_string).collect::<Vec<_>>();
+
+                writeln!(writer, "tx, {}, {}, {}, {}, {}, {}",
+                    tx_id, transaction.amount(), transaction.state(), history.join(" "), transaction.disputed(), transaction.charged_back(),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a snapshot written by `Snapshot::write`, of the current or an earlier version
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+
+        let header = lines.next().transpose()?.unwrap_or_default();
+
+        let version = match header.strip_prefix(MAGIC).map(|version| version.trim().parse::<u32>()) {
+            Some(Ok(version)) if (1..=VERSION).contains(&version) => version,
+            Some(Ok(version)) => return Err(invalid(1, &format!("unsupported version {}", version))),
+            _ => return Err(invalid(1, "not a snapshot file")),
+        };
+
+        // Written on the second line since version 5
+        let mut policy = if version < 5 { Some(Policy::default()) } else { None };
+
+        let mut journal = None;
+
+        let mut accounts = BTreeMap::new();
+        // Account whose transactions are read with the line number it started on
+        let mut current: Option<(usize, AccountLine)> = None;
+        let mut transactions = BTreeMap::new();
+
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            let line_number = index + 2;
+
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+
+            let optional = |field: &str| if field.is_empty() { Ok(None) } else { parse(line_number, field).map(Some) };
+
+            match fields.as_slice() {
+                ["policy", dispute, max_dispute_cycles] if version >= 5 && line_number == 2 => {
+                    let max_dispute_cycles = parse(line_number, max_dispute_cycles)?;
+
+                    if max_dispute_cycles == 0 {
+                        return Err(invalid(line_number, "no dispute cycles"));
+                    }
+
+                    policy = Some(Policy {
+                        dispute: parse(line_number, dispute)?,
+                        max_dispute_cycles,
+                    });
+                },
+                _ if policy.is_none() => return Err(invalid(line_number, "missing policy")),
+                ["journal", run, seqs] if version >= 6 && line_number == 3 => {
+                    journal = Some(JournalPosition {
+                        run:    parse(line_number, run)?,
+                        seqs:   seqs.split_whitespace().map(|seq| parse(line_number, seq)).collect::<io::Result<_>>()?,
+                    });
+                },
+                ["account", client_id, state, available, held, rest @ ..] => {
+                    if let Some((start, account)) = current.take() {
+                        let (client_id, account) = account.into_account(start, std::mem::take(&mut transactions))?;
+
+                        if accounts.insert(client_id, account).is_some() {
+                            return Err(invalid(start, "duplicate account"));
+                        }
+                    }
+
+                    let locked = match *state {
+                        "active" => false,
+                        "locked" => true,
+                        _ => return Err(invalid(line_number, "unknown account state")),
+                    };
+
+                    let mut account = AccountLine {
+                        client_id:      parse(line_number, client_id)?,
+                        locked,
+                        available:      parse(line_number, available)?,
+                        held:           parse(line_number, held)?,
+                        lock_tx:        None,
+                        counts:         None,
+                        last_activity:  None,
+                    };
+
+                    match (version, rest) {
+                        (1, []) => {},
+                        (2..=6, [lock_tx, deposits, withdrawals, disputes, resolves, chargebacks, last_activity]) => {
+                            account.lock_tx = optional(lock_tx)?;
+                            account.counts = Some(TransactionCounts {
+                                deposits:       parse(line_number, deposits)?,
+                                withdrawals:    parse(line_number, withdrawals)?,
+                                disputes:       parse(line_number, disputes)?,
+                                resolves:       parse(line_number, resolves)?,
+                                chargebacks:    parse(line_number, chargebacks)?,
+                            });
+                            account.last_activity = optional(last_activity)?;
+                        },
+                        _ => return Err(invalid(line_number, "malformed line")),
+                    }
+
+                    current = Some((line_number, account));
+                },
+                ["tx", tx_id, amount, state, rest @ ..] => {
+                    if current.is_none() {
+                        return Err(invalid(line_number, "transaction without an account"));
+                    }
+
+                    let amount = parse::<TxAmount>(line_number, amount)?;
+                    let state = parse::<TransactionState>(line_number, state)?;
+
+                    let parse_history = |history: &str| history.split_whitespace().map(|state| parse(line_number, state)).collect::<io::Result<_>>();
+
+                    // Before version 4 disputes and chargebacks always covered the whole amount
+                    let whole = |covered| if covered == state { amount } else { TxAmount::zero() };
+
+                    let (history, disputed, charged_back) = match (version, rest) {
+                        (1..=2, []) => (implied_history(state), whole(TransactionState::Disputed), whole(TransactionState::Chargeback)),
+                        (3, [history]) => (parse_history(history)?, whole(TransactionState::Disputed), whole(TransactionState::Chargeback)),
+                        (4..=6, [history, disputed, charged_back]) => (parse_history(history)?, parse(line_number, disputed)?, parse(line_number, charged_back)?),
+                        _ => return Err(invalid(line_number, "malformed line")),
+                    };
+
+                    let valid_history = std::iter::once(&TransactionState::New).chain(history.iter())
+                        .zip(history.iter())
+                        .all(|(previous, next)| previous.can_transition_to(*next));
+
+                    if !valid_history {
+                        return Err(invalid(line_number, "invalid transaction history"));
+                    }
+
+                    check_amounts(line_number, amount, state, disputed, charged_back)?;
+
+                    let transaction = Transaction::restore(amount, history, disputed, charged_back);
+
+                    if transaction.state() != state {
+                        return Err(invalid(line_number, "transaction state does not match its history"));
+                    }
+
+                    if transactions.insert(parse(line_number, tx_id)?, transaction).is_some() {
+                        return Err(invalid(line_number, "duplicate transaction"));
+                    }
+                },
+                [""] => {},
+                _ => return Err(invalid(line_number, "malformed line")),
+            }
+        }
+
+        if let Some((start, account)) = current {
+            let (client_id, account) = account.into_account(start, transactions)?;
+
+            if accounts.insert(client_id, account).is_some() {
+                return Err(invalid(start, "duplicate account"));
+            }
+        }
+
+        Ok(Snapshot {
+            policy: policy.ok_or_else(|| invalid(2, "missing policy"))?,
+            journal,
+            accounts,
+        })
+    }
+}
+
+/// History of a transaction of a version 1 or 2 snapshot, which went through at most one dispute cycle
+fn implied_history(state: TransactionState) -> Vec<TransactionState> {
+    match state {
+        TransactionState::New => Vec::new(),
+        TransactionState::Disputed => vec![TransactionState::Disputed],
+        TransactionState::Resolved | TransactionState::Chargeback => vec![TransactionState::Disputed, state],
+    }
+}
+
+/// Checks that the disputed and charged back amounts are signed like the amount and do not exceed it,
+/// and that only a disputed transaction has a disputed amount and only a charged back one a charged back amount
+fn check_amounts(line: usize, amount: TxAmount, state: TransactionState, disputed: TxAmount, charged_back: TxAmount) -> io::Result<()> {
+    if disputed != TxAmount::zero() && state != TransactionState::Disputed {
+        return Err(invalid(line, "disputed amount without a dispute"));
+    }
+
+    if charged_back != TxAmount::zero() && state != TransactionState::Chargeback {
+        return Err(invalid(line, "charged back amount without a chargeback"));
+    }
+
+    // Withdrawals are compared like deposits
+    let magnitude = |value: TxAmount| if amount < TxAmount::zero() { value.checked_neg() } else { Some(value) };
+
+    match (magnitude(amount), magnitude(disputed), magnitude(charged_back)) {
+        (Some(amount), Some(disputed), Some(charged_back))
+            if disputed >= TxAmount::zero() && charged_back >= TxAmount::zero() && charged_back <= amount && disputed <= amount - charged_back => Ok(()),
+        _ => Err(invalid(line, "disputed or charged back amount does not fit the amount")),
+    }
+}
+
+/// Path of the temporary file which is written before it replaces the file at path
+pub fn temporary_path(path: &Path) -> PathBuf {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    PathBuf::from(temporary)
+}
+
+/// Replaces the file at path with the completely written temporary file
+///
+/// The temporary file is synced, renamed and the rename is synced, the file either contains its previous
+/// or the new content. The temporary file is removed if it cannot be moved into place
+pub fn replace_file(temporary: &Path, path: &Path) -> io::Result<()> {
+    let renamed = File::open(temporary).and_then(|file| file.sync_all()).and_then(|_| fs::rename(temporary, path));
+
+    if let Err(e) = renamed {
+        let _ = fs::remove_file(temporary);
+
+        return Err(e);
+    }
+
+    sync_directory(path)
+}
+
+/// Syncs the directory of a renamed file, the rename is not durable before
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    File::open(directory)?.sync_all()
+}
+
+/// Directories cannot be opened as files on other platforms
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn invalid(line: usize, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid snapshot at line {}: {}", line, reason))
+}
+
+fn parse<T: std::str::FromStr>(line: usize, field: &str) -> io::Result<T> {
+    field.parse().map_err(|_| invalid(line, &format!("invalid field {:?}", field)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        LedgerAction, LedgerItem, TxAmount, account::{LockReason, TransactionCounts}, journal::JournalPosition, policy::{DisputePolicy, Policy},
+        transaction::TransactionState,
+    };
+
+    use super::Snapshot;
+
+    #[test]
+    fn roundtrip() {
+        let policy = Policy { dispute: DisputePolicy::PendingCredit, max_dispute_cycles: 3 };
+
+        let mut snapshot = Snapshot::new(policy);
+
+        snapshot.journal = Some(JournalPosition { run: 42, seqs: vec![3, 0, 8] });
+
+        let items = [
+            LedgerItem { client_id: 1, tx_id: 1, action: LedgerAction::Deposit(TxAmount::new(25000)) },
+            LedgerItem { client_id: 1, tx_id: 2, action: LedgerAction::Withdrawal(TxAmount::new(5000)) },
+            LedgerItem { client_id: 1, tx_id: 1, action: LedgerAction::Dispute(None) },
+            LedgerItem { client_id: 2, tx_id: 3, action: LedgerAction::Deposit(TxAmount::new(10000)) },
+            LedgerItem { client_id: 2, tx_id: 3, action: LedgerAction::Dispute(None) },
+            LedgerItem { client_id: 2, tx_id: 3, action: LedgerAction::Chargeback(None) },
+            LedgerItem { client_id: 3, tx_id: 4, action: LedgerAction::Deposit(TxAmount::new(10000)) },
+            LedgerItem { client_id: 3, tx_id: 4, action: LedgerAction::Dispute(Some(TxAmount::new(4000))) },
+        ];
+
+        for item in items.iter() {
+            snapshot.accounts.entry(item.client_id).or_default().process(*item).unwrap();
+        }
+
+        let path = std::env::temp_dir().join(format!("transaction-processor-snapshot-{}.snapshot", std::process::id()));
+
+        snapshot.write(&path).unwrap();
+
+        let restored = Snapshot::read(&path).unwrap();
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored.policy, policy);
+        assert_eq!(restored.journal, Some(JournalPosition { run: 42, seqs: vec![3, 0, 8] }));
+        assert_eq!(restored.accounts.len(), 3);
+
+        let account = &restored.accounts[&1];
+
+        assert!(!account.is_locked());
+        assert_eq!((account.available(), account.held()), (TxAmount::new(-5000), TxAmount::new(25000)));
+
+        // The restored dispute can be resolved
+        let mut account = account.clone();
+
+        account.process(LedgerItem { client_id: 1, tx_id: 1, action: LedgerAction::Resolve }).unwrap();
+
+        assert_eq!(account.available(), TxAmount::new(20000));
+        assert_eq!(account.transactions().next().unwrap().1.history(), [TransactionState::Disputed, TransactionState::Resolved]);
+
+        assert_eq!(account.counts(), TransactionCounts { deposits: 1, withdrawals: 1, disputes: 1, resolves: 1, chargebacks: 0 });
+        assert_eq!(account.last_activity(), Some(1));
+
+        let account = &restored.accounts[&2];
+
+        assert!(account.is_locked());
+        assert_eq!(account.lock_reason(), Some(LockReason::Chargeback(3)));
+        assert_eq!(account.total(), TxAmount::zero());
+        assert_eq!(account.counts(), TransactionCounts { deposits: 1, withdrawals: 0, disputes: 1, resolves: 0, chargebacks: 1 });
+
+        // The partial dispute can be charged back up to the disputed amount
+        let mut account = restored.accounts[&3].clone();
+
+        assert_eq!((account.available(), account.held()), (TxAmount::new(6000), TxAmount::new(4000)));
+        assert!(account.process(LedgerItem { client_id: 3, tx_id: 4, action: LedgerAction::Chargeback(Some(TxAmount::new(5000))) }).is_err());
+
+        account.process(LedgerItem { client_id: 3, tx_id: 4, action: LedgerAction::Chargeback(None) }).unwrap();
+
+        assert_eq!((account.available(), account.held()), (TxAmount::new(6000), TxAmount::zero()));
+    }
+
+    #[test]
+    fn version_1() {
+        let path = std::env::temp_dir().join(format!("transaction-processor-version-1-{}.snapshot", std::process::id()));
+
+        fs::write(&path, "\
+transaction-processor snapshot 1
+account, 1, active, 1.5000, 0.5000
+tx, 1, 2.5000, New
+tx, 2, -0.5000, Resolved
+tx, 3, 0.5000, Disputed
+account, 2, locked, 0.0000, 0.0000
+tx, 4, 1.0000, Chargeback
+").unwrap();
+
+        let snapshot = Snapshot::read(&path).unwrap();
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(snapshot.policy, Policy::default());
+
+        let account = &snapshot.accounts[&1];
+
+        assert_eq!((account.available(), account.held()), (TxAmount::new(15000), TxAmount::new(5000)));
+        assert_eq!(account.counts(), TransactionCounts { deposits: 2, withdrawals: 1, disputes: 2, resolves: 1, chargebacks: 0 });
+        assert_eq!(account.last_activity(), None);
+
+        assert_eq!(snapshot.accounts[&2].lock_reason(), Some(LockReason::Chargeback(4)));
+    }
+
+    #[test]
+    fn invalid() {
+        let path = std::env::temp_dir().join(format!("transaction-processor-invalid-{}.snapshot", std::process::id()));
+
+        fs::write(&path, "transaction-processor snapshot 7\n").unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        // The journal position follows the policy, its sequence numbers are unsigned
+        for journal in ["journal, 1, 2 3\npolicy, symmetric, 1", "policy, symmetric, 1\njournal, 1, 2 -3"].iter() {
+            fs::write(&path, format!("transaction-processor snapshot 6\n{}\n", journal)).unwrap();
+            assert!(Snapshot::read(&path).is_err(), "{}", journal);
+        }
+
+        // Version 5 snapshots start with the policy
+        fs::write(&path, "transaction-processor snapshot 5\n").unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        fs::write(&path, "transaction-processor snapshot 5\naccount, 1, active, 0.0000, 0.0000, , 0, 0, 0, 0, 0, \npolicy, symmetric, 1\n").unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        for policy in ["lenient, 1", "symmetric, 0", "symmetric"].iter() {
+            fs::write(&path, format!("transaction-processor snapshot 5\npolicy, {}\n", policy)).unwrap();
+            assert!(Snapshot::read(&path).is_err(), "{}", policy);
+        }
+
+        fs::write(&path, "transaction-processor snapshot 1\ntx, 1, 1.0000, New\n").unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        fs::write(&path, "transaction-processor snapshot 1\naccount, 1, active, 1.0000, 0.0000\ntx, 1, 1.0000, Unknown\n").unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        // Version 2 account lines carry the statistics
+        fs::write(&path, "transaction-processor snapshot 2\naccount, 1, active, 1.0000, 0.0000\n").unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        // Version 3 transaction lines carry the history, which has to end in the state
+        let account = "account, 1, active, 0.0000, 1.0000, , 1, 0, 2, 1, 0, 1";
+
+        fs::write(&path, format!("transaction-processor snapshot 3\n{}\ntx, 1, 1.0000, Disputed\n", account)).unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        fs::write(&path, format!("transaction-processor snapshot 3\n{}\ntx, 1, 1.0000, Disputed, Disputed Resolved\n", account)).unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        // Every state of the history has to follow from the previous one, starting from New
+        for history in ["Resolved", "Disputed Disputed", "Resolved Disputed", "Disputed Chargeback Disputed", "New Disputed"].iter() {
+            fs::write(&path, format!("transaction-processor snapshot 3\n{}\ntx, 1, 1.0000, Disputed, {}\n", account, history)).unwrap();
+            assert!(Snapshot::read(&path).is_err(), "{}", history);
+        }
+
+        fs::write(&path, format!("transaction-processor snapshot 3\n{}\ntx, 1, 1.0000, Disputed, Disputed Resolved Disputed\n", account)).unwrap();
+        assert_eq!(Snapshot::read(&path).unwrap().accounts[&1].transactions().next().unwrap().1.dispute_cycles(), 2);
+
+        // Version 4 transaction lines carry the disputed and charged back amounts, which have to fit the amount and state
+        let account = "account, 1, active, -1.0000, 0.0000, , 0, 1, 1, 0, 0, 1";
+
+        for tx in [
+            "tx, 1, -1.0000, Disputed, Disputed, 0.5000, 0.0000",
+            "tx, 1, -1.0000, Disputed, Disputed, -1.5000, 0.0000",
+            "tx, 1, -1.0000, Resolved, Disputed Resolved, -0.5000, 0.0000",
+            "tx, 1, -1.0000, Disputed, Disputed, -0.5000, -0.5000",
+            "tx, 1, -1.0000, Chargeback, Disputed Chargeback, 0.0000, -1.5000",
+        ].iter() {
+            fs::write(&path, format!("transaction-processor snapshot 4\n{}\n{}\n", account, tx)).unwrap();
+            assert!(Snapshot::read(&path).is_err(), "{}", tx);
+        }
+
+        fs::write(&path, format!("transaction-processor snapshot 4\n{}\ntx, 1, -1.0000, Disputed, Disputed, -0.5000, 0.0000\n", account)).unwrap();
+        assert_eq!(Snapshot::read(&path).unwrap().accounts[&1].transactions().next().unwrap().1.disputed(), TxAmount::new(-5000));
+
+        // The total funds of an account have to be representable
+        fs::write(&path, format!("transaction-processor snapshot 1\naccount, 1, active, {}, 0.0000\n", TxAmount::MAX)).unwrap();
+        assert!(Snapshot::read(&path).is_ok());
+
+        fs::write(&path, format!("transaction-processor snapshot 1\naccount, 1, active, {}, 1.0000\n", TxAmount::MAX)).unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        // Accounts and their transactions appear only once
+        fs::write(&path, "transaction-processor snapshot 1\naccount, 1, active, 1.0000, 0.0000\naccount, 1, active, 2.0000, 0.0000\n").unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        fs::write(&path, "transaction-processor snapshot 1\naccount, 1, active, 1.0000, 0.0000\ntx, 1, 1.0000, New\ntx, 1, 1.0000, New\n").unwrap();
+        assert!(Snapshot::read(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,13 +1,14 @@
-use std::fmt::{Display};
+use std::{fmt::Display, str::FromStr};
 
-use crate::*;
+use crate::{*, policy::{DisputePolicy, Policy}};
 
+/// Change to the balances of an account caused by a transaction
 pub struct TransactionDelta {
     pub available: TxAmount,
     pub held:      TxAmount,
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
 pub enum TransactionState {
     New,
     Disputed,
@@ -15,95 +16,245 @@ pub enum TransactionState {
     Chargeback,
 }
 
+/// A deposit or withdrawal which can be disputed, resolved and charged back
+///
+/// Disputes and chargebacks can cover part of the amount, the disputed and charged back amounts are signed like the amount
+#[derive(Debug, Clone)]
 pub struct Transaction {
-    amount: TxAmount,
-    state:  TransactionState,
+    amount:         TxAmount,
+    state:          TransactionState,
+    /// States the transaction went through after `New`
+    history:        Vec<TransactionState>,
+    /// Amount held by the open dispute
+    disputed:       TxAmount,
+    charged_back:   TxAmount,
 }
 
 pub enum TransactionError {
     NegativeDeposit,
     NegativeWithdrawal,
     InvalidTransactionStateTransition(TransactionState, TransactionState),
+    DisputedWithdrawal,
+    /// The partial amount is not positive or exceeds the remaining amount, which is given
+    InvalidPartialAmount(TxAmount),
 }
 
 impl Transaction {
 
+    fn new(amount: TxAmount) -> Self {
+        Transaction {
+            amount,
+            state:          TransactionState::New,
+            history:        Vec::new(),
+            disputed:       TxAmount::zero(),
+            charged_back:   TxAmount::zero(),
+        }
+    }
+
+    /// Recreates a transaction from its stored amounts (negative for withdrawals) and history, e.g. from a snapshot
+    ///
+    /// The state is the last state of the history, `New` for an empty history
+    pub fn restore(amount: TxAmount, history: Vec<TransactionState>, disputed: TxAmount, charged_back: TxAmount) -> Self {
+        Transaction {
+            amount,
+            state: history.last().copied().unwrap_or(TransactionState::New),
+            history,
+            disputed,
+            charged_back,
+        }
+    }
+
+    /// Amount of the transaction, withdrawals are negative
+    pub fn amount(&self) -> TxAmount {
+        self.amount
+    }
+
+    pub fn state(&self) -> TransactionState {
+        self.state
+    }
+
+    /// Amount held by the open dispute, zero unless the transaction is disputed
+    pub fn disputed(&self) -> TxAmount {
+        self.disputed
+    }
+
+    /// Amount reversed by the chargeback
+    pub fn charged_back(&self) -> TxAmount {
+        self.charged_back
+    }
+
+    /// States the transaction went through after `New`, every dispute cycle starts with `Disputed`
+    pub fn history(&self) -> &[TransactionState] {
+        &self.history
+    }
+
+    /// Number of times the transaction was disputed
+    pub fn dispute_cycles(&self) -> usize {
+        self.history.iter().filter(|state| TransactionState::Disputed == **state).count()
+    }
+
+    fn transition(&mut self, state: TransactionState) {
+        self.state = state;
+        self.history.push(state);
+    }
+
     pub fn deposit(amount: TxAmount) -> Result<(Self, TransactionDelta), TransactionError> {
-        if amount < FpIsize::zero() {
+        if amount < TxAmount::zero() {
             Err(TransactionError::NegativeDeposit)
         } else {
             Ok((
-                Transaction {
-                    amount,
-                    state: TransactionState::New,
-                },
+                Transaction::new(amount),
                 TransactionDelta {
                     available:  amount,
-                    held:       FpIsize::zero(),
+                    held:       TxAmount::zero(),
                 }
             ))
         }
     }
 
     pub fn withdraw(amount: TxAmount) -> Result<(Self, TransactionDelta), TransactionError> {
-        if amount < FpIsize::zero() {
+        if amount < TxAmount::zero() {
             Err(TransactionError::NegativeWithdrawal)
         } else {
             let amount = -amount;
 
             Ok((
-                Transaction {
-                    amount,
-                    state: TransactionState::New,
-                },
+                Transaction::new(amount),
                 TransactionDelta {
                     available:  amount,
-                    held:       FpIsize::zero(),
+                    held:       TxAmount::zero(),
                 }
             ))
         }
     }
 
-    pub fn dispute(&mut self) -> Result<TransactionDelta, TransactionError> {
-        if TransactionState::New == self.state {
-            self.state = TransactionState::Disputed;
+    fn is_withdrawal(&self) -> bool {
+        self.amount < TxAmount::zero()
+    }
 
-            Ok(TransactionDelta {
-                available: -self.amount,
-                held:       self.amount
-            })
+    /// Whether the withdrawn amount is held as a pending credit instead of being credited to the available funds
+    fn is_pending_credit(&self, policy: DisputePolicy) -> bool {
+        policy == DisputePolicy::PendingCredit && self.is_withdrawal()
+    }
+
+    /// Part of the limit given by a partial amount, signed like the amount of the transaction
+    ///
+    /// The whole limit without a partial amount, fails if the partial amount is not positive or exceeds the limit
+    fn portion(&self, partial: Option<TxAmount>, limit: TxAmount) -> Result<TxAmount, TransactionError> {
+        let partial = match partial {
+            None => return Ok(limit),
+            Some(partial) => partial,
+        };
+
+        // Withdrawals are compared like deposits, a limit without a positive counterpart is capped at MAX
+        let remaining = if self.is_withdrawal() { limit.checked_neg().unwrap_or(TxAmount::MAX) } else { limit };
+        let invalid = TransactionError::InvalidPartialAmount(remaining);
+
+        if partial <= TxAmount::zero() || partial > remaining {
+            Err(invalid)
+        } else if self.is_withdrawal() {
+            partial.checked_neg().ok_or(invalid)
         } else {
+            Ok(partial)
+        }
+    }
+
+    /// Disputes a new transaction, or a resolved one while it has fewer dispute cycles than the policy allows
+    ///
+    /// Holds the partial amount, or the whole amount which was not charged back without one
+    pub fn dispute(&mut self, policy: Policy, partial: Option<TxAmount>) -> Result<TransactionDelta, TransactionError> {
+        let disputable = match self.state {
+            TransactionState::New => true,
+            TransactionState::Resolved => self.dispute_cycles() < policy.max_dispute_cycles,
+            _ => false,
+        };
+
+        if !disputable {
             Err(TransactionError::InvalidTransactionStateTransition(self.state, TransactionState::Disputed))
+        } else if policy.dispute == DisputePolicy::DenyWithdrawals && self.is_withdrawal() {
+            Err(TransactionError::DisputedWithdrawal)
+        } else {
+            let disputed = self.portion(partial, self.amount - self.charged_back)?;
+
+            self.transition(TransactionState::Disputed);
+            self.disputed = disputed;
+
+            if self.is_pending_credit(policy.dispute) {
+                Ok(TransactionDelta {
+                    available:  TxAmount::zero(),
+                    held:      -disputed
+                })
+            } else {
+                Ok(TransactionDelta {
+                    available: -disputed,
+                    held:       disputed
+                })
+            }
         }
     }
 
-    pub fn resolve(&mut self) -> Result<TransactionDelta, TransactionError> {
+    /// Releases the disputed amount
+    pub fn resolve(&mut self, policy: Policy) -> Result<TransactionDelta, TransactionError> {
         if TransactionState::Disputed == self.state {
-            self.state = TransactionState::Resolved;
+            let disputed = std::mem::replace(&mut self.disputed, TxAmount::zero());
+
+            self.transition(TransactionState::Resolved);
 
-            Ok(TransactionDelta {
-                available:  self.amount,
-                held:      -self.amount
-            })
+            if self.is_pending_credit(policy.dispute) {
+                Ok(TransactionDelta {
+                    available:  TxAmount::zero(),
+                    held:       disputed
+                })
+            } else {
+                Ok(TransactionDelta {
+                    available:  disputed,
+                    held:      -disputed
+                })
+            }
         } else {
             Err(TransactionError::InvalidTransactionStateTransition(self.state, TransactionState::Resolved))
         }
     }
 
-    pub fn chargeback(&mut self) -> Result<TransactionDelta, TransactionError> {
+    /// Reverses the partial amount, or the whole disputed amount without one, and releases the rest of the disputed amount
+    pub fn chargeback(&mut self, policy: Policy, partial: Option<TxAmount>) -> Result<TransactionDelta, TransactionError> {
         if TransactionState::Disputed == self.state {
-            self.state = TransactionState::Chargeback;
+            let disputed = self.disputed;
+            let charged_back = self.portion(partial, disputed)?;
 
-            Ok(TransactionDelta {
-                available:  FpIsize::zero(),
-                held:      -self.amount
-            })
+            self.transition(TransactionState::Chargeback);
+            self.disputed = TxAmount::zero();
+            self.charged_back += charged_back;
+
+            if self.is_pending_credit(policy.dispute) {
+                Ok(TransactionDelta {
+                    available: -charged_back,
+                    held:       disputed
+                })
+            } else {
+                Ok(TransactionDelta {
+                    available:  disputed - charged_back,
+                    held:      -disputed
+                })
+            }
         } else {
             Err(TransactionError::InvalidTransactionStateTransition(self.state, TransactionState::Chargeback))
         }
     }
 }
 
+impl TransactionState {
+    /// Whether a transaction in this state can go to the next one, ignoring the dispute cycle limit of the policy
+    pub fn can_transition_to(self, next: TransactionState) -> bool {
+        matches!((self, next),
+            (TransactionState::New, TransactionState::Disputed)
+            | (TransactionState::Disputed, TransactionState::Resolved)
+            | (TransactionState::Disputed, TransactionState::Chargeback)
+            | (TransactionState::Resolved, TransactionState::Disputed)
+        )
+    }
+}
+
 impl Display for TransactionState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -113,4 +264,19 @@ impl Display for TransactionState {
             TransactionState::Chargeback => write!(f, "Chargeback"),
         }
     }
-}
\ No newline at end of file
+}
+
+impl FromStr for TransactionState {
+    type Err = &'static str;
+
+    /// Parses the names written by `Display`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "New" => Ok(TransactionState::New),
+            "Disputed" => Ok(TransactionState::Disputed),
+            "Resolved" => Ok(TransactionState::Resolved),
+            "Chargeback" => Ok(TransactionState::Chargeback),
+            _ => Err("Unknown transaction state"),
+        }
+    }
+}